@@ -0,0 +1,86 @@
+//! Measures the per-call cost of `localtime` and `mktime` and how many allocations they make.
+//!
+//! Run with `cargo bench`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const ITERATIONS: u32 = 1_000_000;
+
+fn bench<F: FnMut(u32)>(name: &str, mut f: F) {
+    // warm up, this also loads the zone
+    for i in 0..1000 {
+        f(i);
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for i in 0..ITERATIONS {
+        f(i);
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    let per_call = elapsed / ITERATIONS;
+    println!("{:<40} {:>6} ns/call {:>6.2} allocations/call", name, per_call.as_nanos(), f64::from(allocations as u32) / f64::from(ITERATIONS));
+}
+
+fn bench_threads(name: &str, threads: u32) {
+    let start = Instant::now();
+    let handles = (0..threads)
+        .map(|_| std::thread::spawn(|| {
+            for i in 0..ITERATIONS {
+                rl_localtime::localtime(1_600_000_000 + i64::from(i)).unwrap();
+            }
+        }))
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    // throughput of all the threads together, perfect scaling divides the single-threaded time
+    // by the number of threads
+    let per_call = start.elapsed() / (threads * ITERATIONS);
+    println!("{:<40} {:>6} ns/call", name, per_call.as_nanos());
+}
+
+fn main() {
+    let time = rl_localtime::localtime(1_600_000_000).unwrap();
+
+    std::env::remove_var("TZ");
+    bench("localtime, TZ unset", |i| { rl_localtime::localtime(1_600_000_000 + i64::from(i)).unwrap(); });
+    bench("mktime, TZ unset", |_| { rl_localtime::mktime(time).unwrap(); });
+
+    // std copies the value on every read
+    std::env::set_var("TZ", "Europe/Bratislava");
+    bench("localtime, TZ set", |i| { rl_localtime::localtime(1_600_000_000 + i64::from(i)).unwrap(); });
+    bench("mktime, TZ set", |_| { rl_localtime::mktime(time).unwrap(); });
+
+    rl_localtime::set_env_source(rl_localtime::StaticEnv::new().with("TZ", "Europe/Bratislava"));
+    bench("localtime, TZ set in StaticEnv", |i| { rl_localtime::localtime(1_600_000_000 + i64::from(i)).unwrap(); });
+    bench("mktime, TZ set in StaticEnv", |_| { rl_localtime::mktime(time).unwrap(); });
+    rl_localtime::reset_env_source();
+
+    let zone = rl_localtime::TimeZone::load("Europe/Bratislava").unwrap();
+    bench("TimeZone::to_local", |i| { zone.to_local(1_600_000_000 + i64::from(i)).unwrap(); });
+
+    std::env::remove_var("TZ");
+    let threads = std::thread::available_parallelism().map_or(4, |threads| threads.get().min(8) as u32);
+    bench_threads(&format!("localtime, TZ unset, {} threads", threads), threads);
+}
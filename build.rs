@@ -1,9 +1,14 @@
 fn main() {
+    // cc emits rerun-if-env-changed which disables the default of rerunning on any change
+    println!("cargo:rerun-if-changed=c_lib");
     cc::Build::new()
         .file("c_lib/localtime.c")
         // tm_zone has undocumented lifetime so better turn it off
         .define("NO_TM_ZONE", None)
         .define("STD_INSPIRED", None)
+        // the directory of zone files is searched in Rust code, this is where distributions put
+        // the default zone
+        .define("TZDEFAULT", "\"/etc/localtime\"")
         .warnings(std::env::var_os("RL_LOCALTIME_WARN").is_some())
         .compile("rllocaltime");
 }
@@ -17,9 +17,16 @@
 #include "fcntl.h"
 
 #include <pthread.h>
-static pthread_mutex_t locallock = PTHREAD_MUTEX_INITIALIZER;
-static int lock(void) { return pthread_mutex_lock(&locallock); }
-static void unlock(void) { pthread_mutex_unlock(&locallock); }
+
+/* Strings owned by Rust code, see COsString in src/lib.rs.  */
+typedef struct {
+  char *ptr;
+  size_t len;
+  size_t capacity;
+} rust_os_string_t;
+
+rust_os_string_t rust_zoneinfo_file(const char *, size_t);
+void rust_os_string_dealloc(rust_os_string_t);
 
 /* NETBSD_INSPIRED_EXTERN functions are exported to callers if
    NETBSD_INSPIRED is defined, and are private otherwise.  */
@@ -121,12 +128,12 @@ struct state {
     int           charcnt;
     bool          goback;
     bool          goahead;
-    time_t        ats[TZ_MAX_TIMES];
-    unsigned char types[TZ_MAX_TIMES];
-    struct ttinfo ttis[TZ_MAX_TYPES];
-    char          chars[BIGGEST(BIGGEST(TZ_MAX_CHARS + 1, sizeof gmt),
-                  (2 * (MY_TZNAME_MAX + 1)))];
-    struct lsinfo lsis[TZ_MAX_LEAPS];
+    /* The arrays are allocated by state_resize to fit the data.  */
+    time_t        *ats;
+    unsigned char *types;
+    struct ttinfo *ttis;
+    char          *chars;
+    struct lsinfo *lsis;
     int           defaulttype; /* for early times or if no transitions */
 };
 
@@ -153,41 +160,21 @@ static struct tm *timesub(time_t const *, int_fast32_t, struct state const *,
 			  struct tm *);
 static bool typesequiv(struct state const *, int, int);
 static bool tzparse(char const *, struct state *, bool);
+#ifdef STD_INSPIRED
+static time_t timeoff(struct tm *, long, bool *);
+#endif
 
+/* The local time zone is managed by Rust code, only UT is kept here.  */
 #ifdef ALL_STATE
-static struct state * lclptr;
 static struct state * gmtptr;
 #endif /* defined ALL_STATE */
 
 #ifndef ALL_STATE
-static struct state lclmem;
 static struct state gmtmem;
-#define lclptr      (&lclmem)
 #define gmtptr      (&gmtmem)
 #endif /* State Farm */
 
-#ifndef TZ_STRLEN_MAX
-#define TZ_STRLEN_MAX 255
-#endif /* !defined TZ_STRLEN_MAX */
-
-static char lcl_TZname[TZ_STRLEN_MAX + 1];
-static int  lcl_is_set;
-
-/*
-** Section 4.12.3 of X3.159-1989 requires that
-**  Except for the strftime function, these functions [asctime,
-**  ctime, gmtime, localtime] return values in one of two static
-**  objects: a broken-down time structure and an array of char.
-** Thanks to Paul Eggert for noting this.
-*/
-
-static struct tm	tm;
-
 #if !HAVE_POSIX_DECLS
-char *			tzname[2] = {
-	(char *) wildabbr,
-	(char *) wildabbr
-};
 #ifdef USG_COMPAT
 long			timezone;
 int			daylight;
@@ -209,6 +196,72 @@ init_ttinfo(struct ttinfo *s, int_fast32_t gmtoff, bool isdst, int abbrind)
   s->tt_ttisgmt = false;
 }
 
+/* Initialize *SP to an empty state without any arrays.  */
+static void
+state_init(struct state *sp)
+{
+  sp->leapcnt = sp->timecnt = sp->typecnt = sp->charcnt = 0;
+  sp->ats = NULL;
+  sp->types = NULL;
+  sp->ttis = NULL;
+  sp->chars = NULL;
+  sp->lsis = NULL;
+}
+
+/* Free the arrays of *SP but not *SP itself.  */
+static void
+state_free(struct state *sp)
+{
+  free(sp->ats);
+  free(sp->types);
+  free(sp->ttis);
+  free(sp->chars);
+  free(sp->lsis);
+  state_init(sp);
+}
+
+/* Reallocate one array of a state to COUNT elements of SIZE bytes.  */
+static bool
+resize_array(void *arrayp, int count, size_t size)
+{
+  void *array = *(void **) arrayp;
+  /* Avoid zero-sized allocations, their result is implementation-defined.  */
+  if (count < 1)
+    count = 1;
+  if (SIZE_MAX / size < (size_t) count)
+    return false;
+  array = realloc(array, count * size);
+  if (!array)
+    return false;
+  *(void **) arrayp = array;
+  return true;
+}
+
+/* Resize the arrays of *SP to hold TIMECNT transitions, TYPECNT types,
+   CHARCNT abbreviation characters plus a terminating '\0' and LEAPCNT
+   leap seconds, keeping their contents.  The counts in *SP are not
+   changed.  Return 0 on success, an errno value on failure.  */
+static int
+state_resize(struct state *sp, int timecnt, int typecnt, int charcnt,
+	     int leapcnt)
+{
+  int chars = BIGGEST(charcnt + 1, (int) sizeof gmt);
+  if (! (resize_array(&sp->ats, timecnt, sizeof *sp->ats)
+	 && resize_array(&sp->types, timecnt, sizeof *sp->types)
+	 && resize_array(&sp->ttis, typecnt, sizeof *sp->ttis)
+	 && resize_array(&sp->chars, chars, sizeof *sp->chars)
+	 && resize_array(&sp->lsis, leapcnt, sizeof *sp->lsis)))
+    return ENOMEM;
+  return 0;
+}
+
+/* Shrink the arrays of *SP to the counts stored in it.  */
+static int
+state_fit(struct state *sp)
+{
+  return state_resize(sp, sp->timecnt, sp->typecnt, sp->charcnt, sp->leapcnt);
+}
+
 static int_fast32_t
 detzcode(const char *const codep)
 {
@@ -255,57 +308,6 @@ detzcode64(const char *const codep)
 	return result;
 }
 
-static void
-update_tzname_etc(struct state const *sp, struct ttinfo const *ttisp)
-{
-  tzname[ttisp->tt_isdst] = (char *) &sp->chars[ttisp->tt_abbrind];
-#ifdef USG_COMPAT
-  if (!ttisp->tt_isdst)
-    timezone = - ttisp->tt_gmtoff;
-#endif
-#ifdef ALTZONE
-  if (ttisp->tt_isdst)
-    altzone = - ttisp->tt_gmtoff;
-#endif
-}
-
-static void
-settzname(void)
-{
-	register struct state * const	sp = lclptr;
-	register int			i;
-
-	tzname[0] = tzname[1] = (char *) wildabbr;
-#ifdef USG_COMPAT
-	daylight = 0;
-	timezone = 0;
-#endif /* defined USG_COMPAT */
-#ifdef ALTZONE
-	altzone = 0;
-#endif /* defined ALTZONE */
-	if (sp == NULL) {
-		tzname[0] = tzname[1] = (char *) gmt;
-		return;
-	}
-	/*
-	** And to get the latest zone names into tzname. . .
-	*/
-	for (i = 0; i < sp->typecnt; ++i) {
-		register const struct ttinfo * const	ttisp = &sp->ttis[i];
-		update_tzname_etc(sp, ttisp);
-	}
-	for (i = 0; i < sp->timecnt; ++i) {
-		register const struct ttinfo * const	ttisp =
-							&sp->ttis[
-								sp->types[i]];
-		update_tzname_etc(sp, ttisp);
-#ifdef USG_COMPAT
-		if (ttisp->tt_isdst)
-			daylight = 1;
-#endif /* defined USG_COMPAT */
-	}
-}
-
 static void
 scrub_abbrs(struct state *sp)
 {
@@ -339,123 +341,112 @@ differ_by_repeat(const time_t t1, const time_t t0)
 #endif
 }
 
-/* Input buffer for data read from a compiled tz file.  */
-union input_buffer {
-  /* The first part of the buffer, interpreted as a header.  */
-  struct tzhead tzhead;
+/* Local storage needed for 'tzloadbody'.  The contents of the file
+   are read into a buffer allocated to fit them.  */
+struct local_storage {
+  /* The file name to be opened.  */
+  char fullname[FILENAME_MAX + 1];
+};
 
-  /* The entire buffer.  */
-  char buf[2 * sizeof(struct tzhead) + 2 * sizeof (struct state)
-	   + 4 * TZ_MAX_TIMES];
+/* Sections of TZif data reported when it is invalid.  Keep in sync
+   with TzifSection in src/tzif.rs.  */
+enum tzif_section {
+  TZIF_HEADER = 1,
+  TZIF_TRANSITION_TIMES,
+  TZIF_TRANSITION_TYPES,
+  TZIF_LOCAL_TIME_TYPES,
+  TZIF_LEAP_SECONDS,
+  TZIF_STD_WALL,
+  TZIF_UT_LOCAL
 };
 
-/* Local storage needed for 'tzloadbody'.  */
-union local_storage {
-  /* The file name to be opened.  */
-  char fullname[FILENAME_MAX + 1];
+/* Record that SECTION of TZif data is invalid.  */
+static int
+tzif_invalid(enum tzif_section *sectionp, enum tzif_section section)
+{
+	*sectionp = section;
+	return EINVAL;
+}
 
-  /* The results of analyzing the file's contents after it is opened.  */
-  struct {
-    /* The input buffer.  */
-    union input_buffer u;
+/* Set whether localsub may extrapolate *SP before its first and after
+   its last transition using the Gregorian repeat.  */
+static void
+set_repeats(struct state *sp)
+{
+	register int	i;
 
-    /* A temporary state used for parsing a TZ string in the file.  */
-    struct state st;
-  } u;
-};
+	sp->goback = sp->goahead = false;
+	if (sp->timecnt > 1) {
+		for (i = 1; i < sp->timecnt; ++i)
+			if (typesequiv(sp, sp->types[i], sp->types[0]) &&
+				differ_by_repeat(sp->ats[i], sp->ats[0])) {
+					sp->goback = true;
+					break;
+				}
+		for (i = sp->timecnt - 2; i >= 0; --i)
+			if (typesequiv(sp, sp->types[sp->timecnt - 1],
+				sp->types[i]) &&
+				differ_by_repeat(sp->ats[sp->timecnt - 1],
+				sp->ats[i])) {
+					sp->goahead = true;
+					break;
+		}
+	}
+}
 
-/* Load tz data from the file named NAME into *SP.  Read extended
-   format if DOEXTEND.  Use *LSP for temporary storage.  Return 0 on
-   success, an errno value on failure.  */
+/* Parse NREAD bytes of TZif data in BUF into *SP, allocating its
+   arrays.  Read extended format if DOEXTEND.  BUF is modified.  Return
+   0 on success, an errno value on failure; on EINVAL also store the
+   invalid section into *SECTIONP.  */
 static int
-tzloadbody(char const *name, struct state *sp, bool doextend,
-	   union local_storage *lsp)
+tzparsebody(char *buf, ssize_t nread, struct state *sp, bool doextend,
+	    enum tzif_section *sectionp)
 {
 	register int			i;
-	register int			fid;
 	register int			stored;
-	register ssize_t		nread;
-#if !defined(__BIONIC__)
-	register bool doaccess;
-	register char *fullname = lsp->fullname;
-#endif
-	register union input_buffer *up = &lsp->u.u;
 	register int tzheadsize = sizeof (struct tzhead);
+	/* The header consists of char arrays only so it's never misaligned.  */
+	struct tzhead const *tzhead = (struct tzhead const *) buf;
+	struct state			ts;
+	int				err;
 
 	sp->goback = sp->goahead = false;
 
-	if (! name) {
-		name = TZDEFAULT;
-		if (! name)
-		  return EINVAL;
-	}
-
-#if defined(__BIONIC__)
-	extern int __bionic_open_tzdata(const char*, int32_t*);
-	int32_t entry_length;
-	fid = __bionic_open_tzdata(name, &entry_length);
-#else
-	if (name[0] == ':')
-		++name;
-	doaccess = name[0] == '/';
-	if (!doaccess) {
-		char const *p = TZDIR;
-		if (! p)
-		  return EINVAL;
-		if (sizeof lsp->fullname - 1 <= strlen(p) + strlen(name))
-		  return ENAMETOOLONG;
-		strcpy(fullname, p);
-		strcat(fullname, "/");
-		strcat(fullname, name);
-		/* Set doaccess if '.' (as in "../") shows up in name.  */
-		if (strchr(name, '.'))
-			doaccess = true;
-		name = fullname;
-	}
-	if (doaccess && access(name, R_OK) != 0)
-	  return errno;
-	fid = open(name, OPEN_MODE);
-#endif
-	if (fid < 0)
-	  return errno;
-
-#if defined(__BIONIC__)
-	nread = TEMP_FAILURE_RETRY(read(fid, up->buf, entry_length));
-#else
-	nread = read(fid, up->buf, sizeof up->buf);
-#endif
-	if (nread < tzheadsize) {
-	  int err = nread < 0 ? errno : EINVAL;
-	  close(fid);
-	  return err;
-	}
-	if (close(fid) < 0)
-	  return errno;
+	if (nread < tzheadsize
+	    || memcmp(tzhead->tzh_magic, TZ_MAGIC, sizeof tzhead->tzh_magic) != 0)
+	  return tzif_invalid(sectionp, TZIF_HEADER);
 	for (stored = 4; stored <= 8; stored *= 2) {
-		int_fast32_t ttisstdcnt = detzcode(up->tzhead.tzh_ttisstdcnt);
-		int_fast32_t ttisgmtcnt = detzcode(up->tzhead.tzh_ttisgmtcnt);
-		int_fast32_t leapcnt = detzcode(up->tzhead.tzh_leapcnt);
-		int_fast32_t timecnt = detzcode(up->tzhead.tzh_timecnt);
-		int_fast32_t typecnt = detzcode(up->tzhead.tzh_typecnt);
-		int_fast32_t charcnt = detzcode(up->tzhead.tzh_charcnt);
-		char const *p = up->buf + tzheadsize;
-		if (! (0 <= leapcnt && leapcnt < TZ_MAX_LEAPS
+		int_fast32_t ttisstdcnt = detzcode(tzhead->tzh_ttisstdcnt);
+		int_fast32_t ttisgmtcnt = detzcode(tzhead->tzh_ttisgmtcnt);
+		int_fast32_t leapcnt = detzcode(tzhead->tzh_leapcnt);
+		int_fast32_t timecnt = detzcode(tzhead->tzh_timecnt);
+		int_fast32_t typecnt = detzcode(tzhead->tzh_typecnt);
+		int_fast32_t charcnt = detzcode(tzhead->tzh_charcnt);
+		char const *p = buf + tzheadsize;
+		/* Only the number of types is limited, by the size of the type
+		   indices.  The other counts are limited by the size of the
+		   data.  */
+		if (! (0 <= leapcnt
 		       && 0 < typecnt && typecnt < TZ_MAX_TYPES
-		       && 0 <= timecnt && timecnt < TZ_MAX_TIMES
-		       && 0 <= charcnt && charcnt < TZ_MAX_CHARS
+		       && 0 <= timecnt
+		       && 0 <= charcnt
 		       && (ttisstdcnt == typecnt || ttisstdcnt == 0)
 		       && (ttisgmtcnt == typecnt || ttisgmtcnt == 0)))
-		  return EINVAL;
+		  return tzif_invalid(sectionp, TZIF_HEADER);
+		/* Computed in 64 bits so that it can't overflow.  */
 		if (nread
 		    < (tzheadsize		/* struct tzhead */
-		       + timecnt * stored	/* ats */
+		       + (int_fast64_t) timecnt * stored	/* ats */
 		       + timecnt		/* types */
 		       + typecnt * 6		/* ttinfos */
 		       + charcnt		/* chars */
-		       + leapcnt * (stored + 4)	/* lsinfos */
+		       + (int_fast64_t) leapcnt * (stored + 4)	/* lsinfos */
 		       + ttisstdcnt		/* ttisstds */
 		       + ttisgmtcnt))		/* ttisgmts */
-		  return EINVAL;
+		  return tzif_invalid(sectionp, TZIF_HEADER);
+		err = state_resize(sp, timecnt, typecnt, charcnt, leapcnt);
+		if (err != 0)
+		  return err;
 		sp->leapcnt = leapcnt;
 		sp->timecnt = timecnt;
 		sp->typecnt = typecnt;
@@ -475,7 +466,7 @@ tzloadbody(char const *name, struct state *sp, bool doextend,
 			       ? time_t_min : at);
 			  if (timecnt && attime <= sp->ats[timecnt - 1]) {
 			    if (attime < sp->ats[timecnt - 1])
-			      return EINVAL;
+			      return tzif_invalid(sectionp, TZIF_TRANSITION_TIMES);
 			    sp->types[i - 1] = 0;
 			    timecnt--;
 			  }
@@ -488,7 +479,7 @@ tzloadbody(char const *name, struct state *sp, bool doextend,
 		for (i = 0; i < sp->timecnt; ++i) {
 			unsigned char typ = *p++;
 			if (sp->typecnt <= typ)
-			  return EINVAL;
+			  return tzif_invalid(sectionp, TZIF_TRANSITION_TYPES);
 			if (sp->types[i])
 				sp->types[timecnt++] = typ;
 		}
@@ -502,11 +493,11 @@ tzloadbody(char const *name, struct state *sp, bool doextend,
 			p += 4;
 			isdst = *p++;
 			if (! (isdst < 2))
-			  return EINVAL;
+			  return tzif_invalid(sectionp, TZIF_LOCAL_TIME_TYPES);
 			ttisp->tt_isdst = isdst;
 			abbrind = *p++;
 			if (! (abbrind < sp->charcnt))
-			  return EINVAL;
+			  return tzif_invalid(sectionp, TZIF_LOCAL_TIME_TYPES);
 			ttisp->tt_abbrind = abbrind;
 		}
 		for (i = 0; i < sp->charcnt; ++i)
@@ -525,7 +516,7 @@ tzloadbody(char const *name, struct state *sp, bool doextend,
 			 ? time_t_min : tr);
 		    if (leapcnt && trans <= sp->lsis[leapcnt - 1].ls_trans) {
 		      if (trans < sp->lsis[leapcnt - 1].ls_trans)
-			return EINVAL;
+			return tzif_invalid(sectionp, TZIF_LEAP_SECONDS);
 		      leapcnt--;
 		    }
 		    sp->lsis[leapcnt].ls_trans = trans;
@@ -543,7 +534,7 @@ tzloadbody(char const *name, struct state *sp, bool doextend,
 				ttisp->tt_ttisstd = false;
 			else {
 				if (*p != true && *p != false)
-				  return EINVAL;
+				  return tzif_invalid(sectionp, TZIF_STD_WALL);
 				ttisp->tt_ttisstd = *p++;
 			}
 		}
@@ -555,89 +546,75 @@ tzloadbody(char const *name, struct state *sp, bool doextend,
 				ttisp->tt_ttisgmt = false;
 			else {
 				if (*p != true && *p != false)
-						return EINVAL;
+						return tzif_invalid(sectionp, TZIF_UT_LOCAL);
 				ttisp->tt_ttisgmt = *p++;
 			}
 		}
 		/*
 		** If this is an old file, we're done.
 		*/
-		if (up->tzhead.tzh_version[0] == '\0')
+		if (tzhead->tzh_version[0] == '\0')
 			break;
-		nread -= p - up->buf;
-		memmove(up->buf, p, nread);
+		nread -= p - buf;
+		memmove(buf, p, nread);
 	}
 	if (doextend && nread > 2 &&
-		up->buf[0] == '\n' && up->buf[nread - 1] == '\n' &&
+		buf[0] == '\n' && buf[nread - 1] == '\n' &&
 		sp->typecnt + 2 <= TZ_MAX_TYPES) {
-			struct state	*ts = &lsp->u.st;
-
-			up->buf[nread - 1] = '\0';
-			if (tzparse(&up->buf[1], ts, false)
-			    && ts->typecnt == 2) {
-
-			  /* Attempt to reuse existing abbreviations.
-			     Without this, America/Anchorage would stop
-			     working after 2037 when TZ_MAX_CHARS is 50, as
-			     sp->charcnt equals 42 (for LMT CAT CAWT CAPT AHST
-			     AHDT YST AKDT AKST) and ts->charcnt equals 10
-			     (for AKST AKDT).  Reusing means sp->charcnt can
-			     stay 42 in this example.  */
+			buf[nread - 1] = '\0';
+			state_init(&ts);
+			if (tzparse(&buf[1], &ts, false)
+			    && ts.typecnt == 2) {
+
+			  /* Attempt to reuse existing abbreviations so
+			     that they are not stored twice.  */
 			  int gotabbr = 0;
 			  int charcnt = sp->charcnt;
+			  err = state_resize(sp, sp->timecnt + ts.timecnt,
+					     sp->typecnt + 2,
+					     sp->charcnt + ts.charcnt,
+					     sp->leapcnt);
+			  if (err != 0) {
+			    state_free(&ts);
+			    return err;
+			  }
 			  for (i = 0; i < 2; i++) {
-			    char *tsabbr = ts->chars + ts->ttis[i].tt_abbrind;
+			    char *tsabbr = ts.chars + ts.ttis[i].tt_abbrind;
 			    int j;
 			    for (j = 0; j < charcnt; j++)
 			      if (strcmp(sp->chars + j, tsabbr) == 0) {
-				ts->ttis[i].tt_abbrind = j;
+				ts.ttis[i].tt_abbrind = j;
 				gotabbr++;
 				break;
 			      }
 			    if (! (j < charcnt)) {
 			      int tsabbrlen = strlen(tsabbr);
-			      if (j + tsabbrlen < TZ_MAX_CHARS) {
-				strcpy(sp->chars + j, tsabbr);
-				charcnt = j + tsabbrlen + 1;
-				ts->ttis[i].tt_abbrind = j;
-				gotabbr++;
-			      }
+			      strcpy(sp->chars + j, tsabbr);
+			      charcnt = j + tsabbrlen + 1;
+			      ts.ttis[i].tt_abbrind = j;
+			      gotabbr++;
 			    }
 			  }
 			  if (gotabbr == 2) {
 			    sp->charcnt = charcnt;
-			    for (i = 0; i < ts->timecnt; i++)
-			      if (sp->ats[sp->timecnt - 1] < ts->ats[i])
+			    for (i = 0; i < ts.timecnt; i++)
+			      if (sp->timecnt == 0
+				  || sp->ats[sp->timecnt - 1] < ts.ats[i])
 				break;
-			    while (i < ts->timecnt
-				   && sp->timecnt < TZ_MAX_TIMES) {
-			      sp->ats[sp->timecnt] = ts->ats[i];
+			    while (i < ts.timecnt) {
+			      sp->ats[sp->timecnt] = ts.ats[i];
 			      sp->types[sp->timecnt] = (sp->typecnt
-							+ ts->types[i]);
+							+ ts.types[i]);
 			      sp->timecnt++;
 			      i++;
 			    }
-			    sp->ttis[sp->typecnt++] = ts->ttis[0];
-			    sp->ttis[sp->typecnt++] = ts->ttis[1];
+			    sp->ttis[sp->typecnt++] = ts.ttis[0];
+			    sp->ttis[sp->typecnt++] = ts.ttis[1];
 			  }
 			}
+			state_free(&ts);
 	}
-	if (sp->timecnt > 1) {
-		for (i = 1; i < sp->timecnt; ++i)
-			if (typesequiv(sp, sp->types[i], sp->types[0]) &&
-				differ_by_repeat(sp->ats[i], sp->ats[0])) {
-					sp->goback = true;
-					break;
-				}
-		for (i = sp->timecnt - 2; i >= 0; --i)
-			if (typesequiv(sp, sp->types[sp->timecnt - 1],
-				sp->types[i]) &&
-				differ_by_repeat(sp->ats[sp->timecnt - 1],
-				sp->ats[i])) {
-					sp->goahead = true;
-					break;
-		}
-	}
+	set_repeats(sp);
 	/*
 	** If type 0 is is unused in transitions,
 	** it's the type to use for early times.
@@ -672,16 +649,149 @@ tzloadbody(char const *name, struct state *sp, bool doextend,
 			}
 	}
 	sp->defaulttype = i;
+	return state_fit(sp);
+}
+
+/* Read the whole file FID into a buffer allocated to fit it.  Store
+   the buffer into *BUFP and its length into *NREADP.  Return 0 on
+   success, an errno value on failure.  */
+static int
+read_all(int fid, char **bufp, ssize_t *nreadp)
+{
+	size_t size = 4096;
+	ssize_t nread = 0;
+	char *buf = malloc(size);
+
+	if (!buf)
+	  return errno;
+	for (;;) {
+		ssize_t r;
+		if ((size_t) nread == size) {
+			char *grown;
+			if ((size_t) SSIZE_MAX / 2 < size) {
+				free(buf);
+				return EFBIG;
+			}
+			size *= 2;
+			grown = realloc(buf, size);
+			if (!grown) {
+				free(buf);
+				return ENOMEM;
+			}
+			buf = grown;
+		}
+		r = read(fid, buf + nread, size - nread);
+		if (r < 0) {
+			int err = errno;
+			if (err == EINTR)
+				continue;
+			free(buf);
+			return err;
+		}
+		if (r == 0)
+			break;
+		nread += r;
+	}
+	*bufp = buf;
+	*nreadp = nread;
 	return 0;
 }
 
+/* Load tz data from the file named NAME into *SP.  Read extended
+   format if DOEXTEND.  Use *LSP for temporary storage.  Return 0 on
+   success, an errno value on failure.  */
+static int
+tzloadbody(char const *name, struct state *sp, bool doextend,
+	   struct local_storage *lsp)
+{
+	register int			fid;
+	ssize_t				nread;
+	char				*buf;
+	int				err;
+	enum tzif_section		section;
+#if !defined(__BIONIC__)
+	register bool doaccess;
+	register char *fullname = lsp->fullname;
+#endif
+	register int tzheadsize = sizeof (struct tzhead);
+
+	if (! name) {
+		name = TZDEFAULT;
+		if (! name)
+		  return EINVAL;
+	}
+
+#if defined(__BIONIC__)
+	extern int __bionic_open_tzdata(const char*, int32_t*);
+	int32_t entry_length;
+	fid = __bionic_open_tzdata(name, &entry_length);
+#else
+	if (name[0] == ':')
+		++name;
+	doaccess = name[0] == '/';
+	if (!doaccess) {
+		/* Find the file in the zoneinfo search path.  */
+		rust_os_string_t path = rust_zoneinfo_file(name, strlen(name));
+		if (! path.ptr)
+		  return EINVAL;
+		if (sizeof lsp->fullname < path.len) {
+		  rust_os_string_dealloc(path);
+		  return ENAMETOOLONG;
+		}
+		memcpy(fullname, path.ptr, path.len);
+		rust_os_string_dealloc(path);
+		/* Set doaccess if '.' (as in "../") shows up in name.  */
+		if (strchr(name, '.'))
+			doaccess = true;
+		name = fullname;
+	}
+	if (doaccess && access(name, R_OK) != 0)
+	  return errno;
+	fid = open(name, OPEN_MODE);
+#endif
+	if (fid < 0)
+	  return errno;
+
+#if defined(__BIONIC__)
+	buf = malloc(entry_length);
+	if (!buf) {
+	  err = errno;
+	  close(fid);
+	  return err;
+	}
+	nread = TEMP_FAILURE_RETRY(read(fid, buf, entry_length));
+	err = nread < 0 ? errno : 0;
+	if (err != 0)
+	  free(buf);
+#else
+	err = read_all(fid, &buf, &nread);
+#endif
+	if (err != 0) {
+	  close(fid);
+	  return err;
+	}
+	if (nread < tzheadsize) {
+	  free(buf);
+	  close(fid);
+	  return EINVAL;
+	}
+	if (close(fid) < 0) {
+	  err = errno;
+	  free(buf);
+	  return err;
+	}
+	err = tzparsebody(buf, nread, sp, doextend, &section);
+	free(buf);
+	return err;
+}
+
 /* Load tz data from the file named NAME into *SP.  Read extended
    format if DOEXTEND.  Return 0 on success, an errno value on failure.  */
 static int
 tzload(char const *name, struct state *sp, bool doextend)
 {
 #ifdef ALL_STATE
-  union local_storage *lsp = malloc(sizeof *lsp);
+  struct local_storage *lsp = malloc(sizeof *lsp);
   if (!lsp)
     return errno;
   else {
@@ -690,7 +800,7 @@ tzload(char const *name, struct state *sp, bool doextend)
     return err;
   }
 #else
-  union local_storage ls;
+  struct local_storage ls;
   return tzloadbody(name, sp, doextend, &ls);
 #endif
 }
@@ -1043,11 +1153,19 @@ tzparse(const char *name, struct state *sp, bool lastditch)
 		  return false;
 	}
 	charcnt = stdlen + 1;
-	if (sizeof sp->chars < charcnt)
+	if (2 * (MY_TZNAME_MAX + 1) < charcnt)
 	  return false;
 	load_ok = tzload(TZDEFRULES, sp, false) == 0;
-	if (!load_ok)
+	if (!load_ok) {
 		sp->leapcnt = 0;		/* so, we're off a little */
+		sp->timecnt = sp->typecnt = sp->charcnt = 0;
+	}
+	/* Make room for the most the rules below can need, the arrays are
+	   shrunk to fit at the end.  */
+	if (state_resize(sp, BIGGEST(sp->timecnt, TZ_MAX_TIMES),
+			 BIGGEST(sp->typecnt, 2),
+			 2 * (MY_TZNAME_MAX + 1), sp->leapcnt) != 0)
+	  return false;
 	if (*name != '\0') {
 		if (*name == '<') {
 			dstname = ++name;
@@ -1064,7 +1182,7 @@ tzparse(const char *name, struct state *sp, bool lastditch)
 		if (!dstlen)
 		  return false;
 		charcnt += dstlen + 1;
-		if (sizeof sp->chars < charcnt)
+		if (2 * (MY_TZNAME_MAX + 1) < charcnt)
 		  return false;
 		if (*name != '\0' && *name != ',' && *name != ';') {
 			name = getoffset(name, &dstoffset);
@@ -1235,7 +1353,7 @@ tzparse(const char *name, struct state *sp, bool lastditch)
 		memcpy(cp, dstname, dstlen);
 		*(cp + dstlen) = '\0';
 	}
-	return true;
+	return state_fit(sp) == 0;
 }
 
 static void
@@ -1245,10 +1363,19 @@ gmtload(struct state *const sp)
 		tzparse(gmt, sp, true);
 }
 
-/* Initialize *SP to a value appropriate for the TZ setting NAME.
-   Return 0 on success, an errno value on failure.  */
+/* Where a zone came from, reported to Rust code.  Keep in sync with
+   ZoneKind in src/zone.rs.  */
+enum zone_origin {
+  ZONE_ORIGIN_UTC = 1,
+  ZONE_ORIGIN_FILE,
+  ZONE_ORIGIN_POSIX
+};
+
+/* Initialize *SP to a value appropriate for the TZ setting NAME and
+   store where it came from into *ORIGINP.  Return 0 on success, an
+   errno value on failure.  */
 static int
-zoneinit(struct state *sp, char const *name)
+zoneinit(struct state *sp, char const *name, int *originp)
 {
   if (name && ! name[0]) {
     /*
@@ -1259,128 +1386,61 @@ zoneinit(struct state *sp, char const *name)
     sp->typecnt = 0;
     sp->charcnt = 0;
     sp->goback = sp->goahead = false;
+    if (state_resize(sp, 0, 1, 0, 0) != 0)
+      return ENOMEM;
     init_ttinfo(&sp->ttis[0], 0, false, 0);
     strcpy(sp->chars, gmt);
     sp->defaulttype = 0;
+    *originp = ZONE_ORIGIN_UTC;
     return 0;
   } else {
     int err = tzload(name, sp, true);
-    if (err != 0 && name && name[0] != ':' && tzparse(name, sp, false))
+    *originp = ZONE_ORIGIN_FILE;
+    if (err != 0 && name && name[0] != ':' && tzparse(name, sp, false)) {
       err = 0;
+      *originp = ZONE_ORIGIN_POSIX;
+    }
     if (err == 0)
       scrub_abbrs(sp);
     return err;
   }
 }
 
-void
-tzsetlcl(char const *name)
-{
-  struct state *sp = lclptr;
-  int lcl = name ? strlen(name) < sizeof lcl_TZname : -1;
-  if (lcl < 0
-      ? lcl_is_set < 0
-      : 0 < lcl_is_set && strcmp(lcl_TZname, name) == 0)
-    return;
-#ifdef ALL_STATE
-  if (! sp)
-    lclptr = sp = malloc(sizeof *lclptr);
-#endif /* defined ALL_STATE */
-  if (sp) {
-    if (zoneinit(sp, name) != 0)
-      zoneinit(sp, "");
-    if (0 < lcl)
-      strcpy(lcl_TZname, name);
-  }
-  settzname();
-  lcl_is_set = lcl;
-}
+static pthread_once_t gmt_once = PTHREAD_ONCE_INIT;
 
-#ifdef STD_INSPIRED
-void
-tzsetwall(void)
+static void
+gmtinit(void)
 {
-  if (lock() != 0)
-    return;
-  tzsetlcl(NULL);
-  unlock();
-}
+#ifdef ALL_STATE
+  gmtptr = malloc(sizeof *gmtptr);
 #endif
-
-typedef struct {
-  char *ptr;
-  size_t len;
-  size_t capacity;
-} rust_os_string_t;
-
-rust_os_string_t rust_getenv(const char *, size_t);
-void rust_os_string_dealloc(rust_os_string_t);
-
-const char *rust_os_string_ptr(rust_os_string_t str)
-{
-  if (!str.ptr) {
-    return NULL;
-  }
-  if (str.len == 0) {
-    return "";
-  } else {
-    return str.ptr;
-  }
-}
-
-typedef enum {
-  lock_mode_lock = 0,
-  lock_mode_no_lock = 1,
-  lock_mode_keep_locked = 2,
-} lock_mode_t;
-
-static int
-tzset_rust_locked(lock_mode_t lock_mode)
-{
-  rust_os_string_t tz = rust_getenv("TZ", 2);
-  int err = 0;
-  if (lock_mode == lock_mode_no_lock || (err = lock()) == 0) {
-    tzsetlcl(tz.ptr);
-    if (lock_mode == lock_mode_lock) {
-      unlock();
-    }
+  if (gmtptr) {
+    state_init(gmtptr);
+    gmtload(gmtptr);
   }
-  rust_os_string_dealloc(tz);
-  return err;
-}
-
-void
-tzset(void)
-{
-  tzset_rust_locked(lock_mode_lock);
 }
 
 static void
 gmtcheck(void)
 {
-  static bool gmt_is_set;
-  if (lock() != 0)
-    return;
-  if (! gmt_is_set) {
-#ifdef ALL_STATE
-    gmtptr = malloc(sizeof *gmtptr);
-#endif
-    if (gmtptr)
-      gmtload(gmtptr);
-    gmt_is_set = true;
-  }
-  unlock();
+  pthread_once(&gmt_once, gmtinit);
 }
 
 #if NETBSD_INSPIRED
 
+/* Allocate a time zone for the TZ setting NAME, NULL meaning
+   TZDEFAULT.  On success also store the enum zone_origin value into
+   *ORIGINP.  */
 timezone_t
-tzalloc(char const *name)
+rl_tzalloc(char const *name, int *originp)
 {
   timezone_t sp = malloc(sizeof *sp);
   if (sp) {
-    int err = zoneinit(sp, name);
+    int err;
+    state_init(sp);
+    err = zoneinit(sp, name, originp);
     if (err != 0) {
+      state_free(sp);
       free(sp);
       errno = err;
       return NULL;
@@ -1389,9 +1449,52 @@ tzalloc(char const *name)
   return sp;
 }
 
+/* Allocate a time zone from LEN bytes of TZif data in BUF.  On
+   failure return NULL and set errno; if the data is invalid also store
+   the invalid section into *SECTIONP.  */
+timezone_t
+rl_tzalloc_tzif(char const *buf, size_t len, int *sectionp)
+{
+  char *copy;
+  timezone_t sp;
+  enum tzif_section section;
+  int err;
+
+  if ((size_t) SSIZE_MAX < len) {
+    *sectionp = TZIF_HEADER;
+    errno = EINVAL;
+    return NULL;
+  }
+  /* Parsing modifies the buffer.  */
+  copy = malloc(len ? len : 1);
+  if (!copy)
+    return NULL;
+  sp = malloc(sizeof *sp);
+  if (!sp) {
+    free(copy);
+    return NULL;
+  }
+  memcpy(copy, buf, len);
+  state_init(sp);
+  err = tzparsebody(copy, len, sp, true, &section);
+  free(copy);
+  if (err != 0) {
+    state_free(sp);
+    free(sp);
+    if (err == EINVAL)
+      *sectionp = section;
+    errno = err;
+    return NULL;
+  }
+  scrub_abbrs(sp);
+  return sp;
+}
+
 void
-tzfree(timezone_t sp)
+rl_tzfree(timezone_t sp)
 {
+  if (sp)
+    state_free(sp);
   free(sp);
 }
 
@@ -1421,10 +1524,28 @@ tzfree(timezone_t sp)
 ** but it is actually a boolean and its value should be 0 or 1.
 */
 
+/* Copy the abbreviation at ABBRIND of *SP into ABBR of size ABBRSIZE,
+   truncating it if needed.  This is used instead of TM_ZONE because the
+   lifetime of sp->chars is not known to the caller.  */
+static void
+copy_abbr(char *abbr, size_t abbrsize, char const *chars)
+{
+  size_t len;
+
+  if (!abbr || !abbrsize)
+    return;
+  len = strlen(chars);
+  if (abbrsize <= len)
+    len = abbrsize - 1;
+  memcpy(abbr, chars, len);
+  abbr[len] = '\0';
+}
+
 /*ARGSUSED*/
 static struct tm *
-localsub(struct state const *sp, time_t const *timep, int_fast32_t setname,
-	 struct tm *const tmp)
+localsub_abbr(struct state const *sp, time_t const *timep,
+	      int_fast32_t setname, struct tm *const tmp,
+	      char *abbr, size_t abbrsize)
 {
 	register const struct ttinfo *	ttisp;
 	register int			i;
@@ -1433,7 +1554,10 @@ localsub(struct state const *sp, time_t const *timep, int_fast32_t setname,
 
 	if (sp == NULL) {
 	  /* Don't bother to set tzname etc.; tzset has already done it.  */
-	  return gmtsub(gmtptr, timep, 0, tmp);
+	  result = gmtsub(gmtptr, timep, 0, tmp);
+	  if (result)
+	    copy_abbr(abbr, abbrsize, gmtptr ? gmtptr->chars : gmt);
+	  return result;
 	}
 	if ((sp->goback && t < sp->ats[0]) ||
 		(sp->goahead && t > sp->ats[sp->timecnt - 1])) {
@@ -1453,7 +1577,8 @@ localsub(struct state const *sp, time_t const *timep, int_fast32_t setname,
 			if (newt < sp->ats[0] ||
 				newt > sp->ats[sp->timecnt - 1])
 					return NULL;	/* "cannot happen" */
-			result = localsub(sp, &newt, setname, tmp);
+			result = localsub_abbr(sp, &newt, setname, tmp,
+					       abbr, abbrsize);
 			if (result) {
 				register int_fast64_t newy;
 
@@ -1495,53 +1620,152 @@ localsub(struct state const *sp, time_t const *timep, int_fast32_t setname,
 #ifdef TM_ZONE
 	  result->TM_ZONE = (char *) &sp->chars[ttisp->tt_abbrind];
 #endif /* defined TM_ZONE */
-	  if (setname)
-	    update_tzname_etc(sp, ttisp);
+	  copy_abbr(abbr, abbrsize, &sp->chars[ttisp->tt_abbrind]);
 	}
 	return result;
 }
 
+static struct tm *
+localsub(struct state const *sp, time_t const *timep, int_fast32_t setname,
+	 struct tm *const tmp)
+{
+  return localsub_abbr(sp, timep, setname, tmp, NULL, 0);
+}
+
 #if NETBSD_INSPIRED
 
+/* Like localtime_r but uses the time zone SP and copies the abbreviation
+   into ABBR.  */
 struct tm *
-localtime_rz(struct state *sp, time_t const *timep, struct tm *tmp)
+rl_localtime_rz(struct state *sp, time_t const *timep, struct tm *tmp,
+		char *abbr, size_t abbrsize)
 {
-  return localsub(sp, timep, 0, tmp);
+  return localsub_abbr(sp, timep, 0, tmp, abbr, abbrsize);
 }
 
-#endif
+/* Return true if types A and B of *SP differ in anything visible to
+   users of localtime.  */
+static bool
+typesdiffer(struct state const *sp, int a, int b)
+{
+  struct ttinfo const *ap = &sp->ttis[a];
+  struct ttinfo const *bp = &sp->ttis[b];
+  return (ap->tt_gmtoff != bp->tt_gmtoff
+	  || ap->tt_isdst != bp->tt_isdst
+	  || strcmp(&sp->chars[ap->tt_abbrind], &sp->chars[bp->tt_abbrind]) != 0);
+}
 
-static struct tm *
-localtime_tzset(time_t const *timep, struct tm *tmp)
-{
-  // http://b/31339449: POSIX says localtime(3) acts as if it called tzset(3), but upstream
-  // and glibc both think it's okay for localtime_r(3) to not do so (presumably because of
-  // the "not required to set tzname" clause). It's unclear that POSIX actually intended this,
-  // the BSDs disagree with glibc, and it's confusing to developers to have localtime_r(3)
-  // behave differently than other time zone-sensitive functions in <time.h>.
-  int err = tzset_rust_locked(lock_mode_keep_locked);
-  if (err) {
-    errno = err;
-    return NULL;
+/* Find the first stored transition at or after T.  */
+static bool
+stored_transition(struct state const *sp, time_t t, time_t *atp,
+		  int *beforep, int *afterp)
+{
+  int lo = 0;
+  int hi = sp->timecnt;
+
+  while (lo < hi) {
+    int mid = (lo + hi) >> 1;
+    if (sp->ats[mid] < t)
+      lo = mid + 1;
+    else
+      hi = mid;
   }
+  if (lo == sp->timecnt)
+    return false;
+  *atp = sp->ats[lo];
+  *beforep = lo ? sp->types[lo - 1] : sp->defaulttype;
+  *afterp = sp->types[lo];
+  return true;
+}
 
-  tmp = localsub(lclptr, timep, true, tmp);
-  unlock();
-  return tmp;
+/* Store into *SHIFTP the smallest multiple of SECSPERREPEAT greater than
+   SECONDS.  Return false on overflow.  */
+static bool
+repeat_shift(time_t seconds, time_t *shiftp)
+{
+  if (time_t_max / SECSPERREPEAT <= seconds / SECSPERREPEAT)
+    return false;
+  *shiftp = (seconds / SECSPERREPEAT + 1) * SECSPERREPEAT;
+  return true;
 }
 
-struct tm *
-rl_localtime(const time_t *timep)
+/* Find the first transition at or after T including the transitions
+   localsub extrapolates using the Gregorian repeat if goahead or goback
+   is set.  */
+static bool
+any_transition(struct state const *sp, time_t t, time_t *atp,
+	       int *beforep, int *afterp)
 {
-  return localtime_tzset(timep, &tm);
+  time_t last, shift;
+
+  if (sp->timecnt == 0)
+    return false;
+  last = sp->ats[sp->timecnt - 1];
+  if (sp->goahead && t > last) {
+    /* Map T into the last repeat cycle (last - SECSPERREPEAT, last]
+       and shift the result back.  */
+    if (last < 0 && time_t_max + last < t)
+      return false;
+    if (!repeat_shift(t - last - 1, &shift))
+      return false;
+    if (!stored_transition(sp, t - shift, atp, beforep, afterp))
+      return false;
+    if (time_t_max - shift < *atp)
+      return false;
+    *atp += shift;
+    return true;
+  }
+  if (sp->goback && t < sp->ats[0]
+      && !(0 < sp->ats[0] && t < time_t_min + sp->ats[0])
+      && repeat_shift(sp->ats[0] - t, &shift)) {
+    /* Map T into (ats[0], ats[0] + SECSPERREPEAT] so that there is
+       always a stored transition before the result.  */
+    if (stored_transition(sp, t + shift, atp, beforep, afterp)
+	&& *atp - shift < sp->ats[0]) {
+      *atp -= shift;
+      return true;
+    }
+  }
+  return stored_transition(sp, t, atp, beforep, afterp);
 }
 
-struct tm *
-rl_localtime_r(const time_t *timep, struct tm *tmp)
+/* Find the first transition at or after *TIMEP that changes the UT
+   offset, DST flag or abbreviation.  Store its time into *TIMEP and the
+   types in effect before and after it into *BEFOREP and *AFTERP.
+   Return false if there is no such transition.  */
+bool
+rl_next_transition(struct state const *sp, time_t *timep,
+		   int *beforep, int *afterp)
 {
-  return localtime_tzset(timep, tmp);
+  time_t t = *timep;
+  int i;
+
+  /* Give up if a whole cycle of transitions doesn't change anything.  */
+  for (i = 0; i <= sp->timecnt; i++) {
+    if (!any_transition(sp, t, timep, beforep, afterp))
+      return false;
+    if (typesdiffer(sp, *beforep, *afterp))
+      return true;
+    if (*timep == time_t_max)
+      return false;
+    t = *timep + 1;
+  }
+  return false;
 }
 
+/* Store the details of type I of *SP.  */
+void
+rl_zone_type(struct state const *sp, int i, long *gmtoffp, bool *isdstp,
+	     char *abbr, size_t abbrsize)
+{
+  struct ttinfo const *ttisp = &sp->ttis[i];
+  *gmtoffp = ttisp->tt_gmtoff;
+  *isdstp = ttisp->tt_isdst;
+  copy_abbr(abbr, abbrsize, &sp->chars[ttisp->tt_abbrind]);
+}
+
+#endif
+
 /*
 ** gmtsub is to gmtime as localsub is to localtime.
 */
@@ -1565,34 +1789,6 @@ gmtsub(struct state const *sp, time_t const *timep, int_fast32_t offset,
 	return result;
 }
 
-/*
-* Re-entrant version of gmtime.
-*/
-
-struct tm *
-gmtime_r(const time_t *timep, struct tm *tmp)
-{
-  gmtcheck();
-  return gmtsub(gmtptr, timep, 0, tmp);
-}
-
-struct tm *
-gmtime(const time_t *timep)
-{
-  return gmtime_r(timep, &tm);
-}
-
-#ifdef STD_INSPIRED
-
-struct tm *
-offtime(const time_t *timep, long offset)
-{
-  gmtcheck();
-  return gmtsub(gmtptr, timep, offset, &tm);
-}
-
-#endif /* defined STD_INSPIRED */
-
 /*
 ** Return the number of leap years through the end of the given year
 ** where, to make the math easy, the answer for year zero is defined as zero.
@@ -1729,27 +1925,6 @@ timesub(const time_t *timep, int_fast32_t offset,
 	return NULL;
 }
 
-char *
-ctime(const time_t *timep)
-{
-/*
-** Section 4.12.3.2 of X3.159-1989 requires that
-**	The ctime function converts the calendar time pointed to by timer
-**	to local time in the form of a string. It is equivalent to
-**		asctime(localtime(timer))
-*/
-  struct tm *tmp = localtime(timep);
-  return tmp ? asctime(tmp) : NULL;
-}
-
-char *
-ctime_r(const time_t *timep, char *buf)
-{
-  struct tm mytm;
-  struct tm *tmp = rl_localtime_r(timep, &mytm);
-  return tmp ? asctime_r(tmp, buf) : NULL;
-}
-
 /*
 ** Adapted from code provided by Robert Elz, who writes:
 **	The "best" way to do mktime I think is based on an idea of Bob
@@ -2072,7 +2247,8 @@ time1(struct tm *const tmp,
       struct tm *(*funcp) (struct state const *, time_t const *,
 			   int_fast32_t, struct tm *),
       struct state const *sp,
-      const int_fast32_t offset)
+      const int_fast32_t offset,
+      bool *okayp)
 {
 	register time_t			t;
 	register int			samei, otheri;
@@ -2081,16 +2257,16 @@ time1(struct tm *const tmp,
 	register int			nseen;
 	char				seen[TZ_MAX_TYPES];
 	unsigned char			types[TZ_MAX_TYPES];
-	bool				okay;
 
+	*okayp = false;
 	if (tmp == NULL) {
 		errno = EINVAL;
 		return WRONG;
 	}
 	if (tmp->tm_isdst > 1)
 		tmp->tm_isdst = 1;
-	t = time2(tmp, funcp, sp, offset, &okay);
-	if (okay)
+	t = time2(tmp, funcp, sp, offset, okayp);
+	if (*okayp)
 		return t;
 	if (tmp->tm_isdst < 0)
 #ifdef PCTS
@@ -2128,8 +2304,8 @@ time1(struct tm *const tmp,
 			tmp->tm_sec += sp->ttis[otheri].tt_gmtoff -
 					sp->ttis[samei].tt_gmtoff;
 			tmp->tm_isdst = !tmp->tm_isdst;
-			t = time2(tmp, funcp, sp, offset, &okay);
-			if (okay)
+			t = time2(tmp, funcp, sp, offset, okayp);
+			if (*okayp)
 				return t;
 			tmp->tm_sec -= sp->ttis[otheri].tt_gmtoff -
 					sp->ttis[samei].tt_gmtoff;
@@ -2140,70 +2316,49 @@ time1(struct tm *const tmp,
 }
 
 static time_t
-mktime_tzname(struct state *sp, struct tm *tmp, bool setname)
+mktime_tzname(struct state *sp, struct tm *tmp, bool setname, bool *okayp)
 {
   if (sp)
-    return time1(tmp, localsub, sp, setname);
+    return time1(tmp, localsub, sp, setname, okayp);
   else {
     gmtcheck();
-    return time1(tmp, gmtsub, gmtptr, 0);
+    return time1(tmp, gmtsub, gmtptr, 0, okayp);
   }
 }
 
+/* The rl_ versions of mktime-like functions store the result into *OUT
+   and return 0 on success or EOVERFLOW if the time can't be represented
+   because -1 is a valid result.  */
+
 #if NETBSD_INSPIRED
 
-time_t
-rl_mktime_z(struct state *sp, struct tm *tmp)
+int
+rl_mktime_z(struct state *sp, struct tm *tmp, time_t *out)
 {
-  return mktime_tzname(sp, tmp, false);
+  bool okay;
+  *out = mktime_tzname(sp, tmp, false, &okay);
+  return okay ? 0 : EOVERFLOW;
 }
 
 #endif
 
-time_t
-rl_mktime(struct tm *tmp)
-{
-#if defined(__BIONIC__)
-  int saved_errno = errno;
-#endif
-
-  int err = tzset_rust_locked(lock_mode_keep_locked);
-  if (err) {
-    errno = err;
-    return -1;
-  }
-  time_t t = mktime_tzname(lclptr, tmp, true);
-  unlock();
-
-#if defined(__BIONIC__)
-  errno = (t == -1) ? EOVERFLOW : saved_errno;
-#endif
-  return t;
-}
-
 #ifdef STD_INSPIRED
 
-time_t
-timelocal(struct tm *tmp)
+int
+rl_timegm(struct tm *tmp, time_t *out)
 {
-	if (tmp != NULL)
-		tmp->tm_isdst = -1;	/* in case it wasn't initialized */
-	return mktime(tmp);
-}
-
-time_t
-rl_timegm(struct tm *tmp)
-{
-  return timeoff(tmp, 0);
+  bool okay;
+  *out = timeoff(tmp, 0, &okay);
+  return okay ? 0 : EOVERFLOW;
 }
 
 static time_t
-timeoff(struct tm *tmp, long offset)
+timeoff(struct tm *tmp, long offset, bool *okayp)
 {
   if (tmp)
     tmp->tm_isdst = 0;
   gmtcheck();
-  return time1(tmp, gmtsub, gmtptr, offset);
+  return time1(tmp, gmtsub, gmtptr, offset, okayp);
 }
 
 #endif /* defined STD_INSPIRED */
@@ -2238,29 +2393,13 @@ leapcorr(struct state const *sp, time_t t)
 }
 
 NETBSD_INSPIRED_EXTERN time_t ATTRIBUTE_PURE
-time2posix_z(struct state *sp, time_t t)
+rl_time2posix_z(struct state *sp, time_t t)
 {
   return t - leapcorr(sp, t);
 }
 
-time_t
-time2posix(time_t t)
-{
-  int err = lock();
-  if (err) {
-    errno = err;
-    return -1;
-  }
-  if (!lcl_is_set)
-    tzset_rust_locked(lock_mode_no_lock);
-  if (lclptr)
-    t = time2posix_z(lclptr, t);
-  unlock();
-  return t;
-}
-
 NETBSD_INSPIRED_EXTERN time_t ATTRIBUTE_PURE
-posix2time_z(struct state *sp, time_t t)
+rl_posix2time_z(struct state *sp, time_t t)
 {
 	time_t	x;
 	time_t	y;
@@ -2288,20 +2427,98 @@ posix2time_z(struct state *sp, time_t t)
 	return x;
 }
 
-time_t
-posix2time(time_t t)
+/* Store the transition time and correction of leap second I of *SP
+   into *TRANSP and *CORRP.  Return false if there is no such leap
+   second.  */
+bool
+rl_leap_second(struct state const *sp, int i, time_t *transp,
+	       int_fast64_t *corrp)
 {
-  int err = lock();
-  if (err) {
+  if (i < 0 || sp->leapcnt <= i)
+    return false;
+  *transp = sp->lsis[i].ls_trans;
+  *corrp = sp->lsis[i].ls_corr;
+  return true;
+}
+
+/* Add CORR to T saturating at the limits of time_t.  */
+static time_t
+saturating_add(time_t t, int_fast64_t corr)
+{
+  if (corr < 0 ? t < time_t_min - corr : time_t_max - corr < t)
+    return corr < 0 ? time_t_min : time_t_max;
+  return t + corr;
+}
+
+/* Like rl_posix2time_z, but saturating at the limits of time_t.  */
+static time_t
+saturating_posix2time(struct state const *sp, time_t t)
+{
+  time_t x = saturating_add(t, leapcorr(sp, t));
+  time_t y = saturating_add(x, -leapcorr(sp, x));
+  if (y < t) {
+    do {
+      if (x == time_t_max)
+	return x;
+      x++;
+      y = saturating_add(x, -leapcorr(sp, x));
+    } while (y < t);
+    x -= y != t;
+  } else if (y > t) {
+    do {
+      if (x == time_t_min)
+	return x;
+      --x;
+      y = saturating_add(x, -leapcorr(sp, x));
+    } while (y > t);
+    x += y != t;
+  }
+  return x;
+}
+
+/* Allocate a copy of *SRC with its leap seconds replaced by the
+   LEAPCNT ones with transition times TRANS and corrections CORR, which
+   must be sorted.  Transition times are converted to count the new
+   leap seconds.  On failure return NULL and set errno.  */
+timezone_t
+rl_tzalloc_leaps(struct state const *src, time_t const *trans,
+		 int_fast64_t const *corr, int leapcnt)
+{
+  timezone_t sp;
+  int i, err;
+
+  sp = malloc(sizeof *sp);
+  if (!sp)
+    return NULL;
+  state_init(sp);
+  err = state_resize(sp, src->timecnt, src->typecnt, src->charcnt,
+		     leapcnt);
+  if (err != 0) {
+    state_free(sp);
+    free(sp);
     errno = err;
-    return -1;
+    return NULL;
   }
-  if (!lcl_is_set)
-    tzset_rust_locked(lock_mode_no_lock);
-  if (lclptr)
-    t = posix2time_z(lclptr, t);
-  unlock();
-  return t;
+  sp->timecnt = src->timecnt;
+  sp->typecnt = src->typecnt;
+  sp->charcnt = src->charcnt;
+  sp->leapcnt = leapcnt;
+  sp->defaulttype = src->defaulttype;
+  for (i = 0; i < leapcnt; i++) {
+    sp->lsis[i].ls_trans = trans[i];
+    sp->lsis[i].ls_corr = corr[i];
+  }
+  for (i = 0; i < sp->timecnt; i++) {
+    time_t posix = saturating_add(src->ats[i],
+				  -leapcorr(src, src->ats[i]));
+    sp->ats[i] = saturating_posix2time(sp, posix);
+  }
+  memcpy(sp->types, src->types, src->timecnt * sizeof *sp->types);
+  memcpy(sp->ttis, src->ttis, src->typecnt * sizeof *sp->ttis);
+  memcpy(sp->chars, src->chars, src->charcnt);
+  sp->chars[sp->charcnt] = '\0';
+  set_repeats(sp);
+  return sp;
 }
 
 #endif /* defined STD_INSPIRED */
@@ -385,9 +385,6 @@ double difftime(time_t, time_t);
 struct tm *gmtime(time_t const *);
 struct tm *gmtime_r(time_t const *restrict, struct tm *restrict);
 struct tm *localtime(time_t const *);
-*/
-struct tm *rl_localtime_r(time_t const *restrict, struct tm *restrict);
-/*
 time_t mktime(struct tm *);
 time_t time(time_t *);
 void tzset(void);
@@ -419,27 +416,9 @@ extern long altzone;
 */
 
 #ifdef STD_INSPIRED
-# if !defined tzsetwall || defined time_tz
-void tzsetwall(void);
-# endif
-# if !defined offtime || defined time_tz
-struct tm *offtime(time_t const *, long);
-# endif
 # if !defined timegm || defined time_tz
 time_t timegm(struct tm *);
 # endif
-# if !defined timelocal || defined time_tz
-time_t timelocal(struct tm *);
-# endif
-# if !defined timeoff || defined time_tz
-static time_t timeoff(struct tm *, long);
-# endif
-# if !defined time2posix || defined time_tz
-time_t time2posix(time_t);
-# endif
-# if !defined posix2time || defined time_tz
-time_t posix2time(time_t);
-# endif
 #endif
 
 /* Infer TM_ZONE on systems where this information is known, but suppress
@@ -465,18 +444,11 @@ time_t posix2time(time_t);
 */
 #if NETBSD_INSPIRED
 typedef struct state *timezone_t;
-struct tm *localtime_rz(timezone_t restrict, time_t const *restrict,
-			struct tm *restrict);
-time_t mktime_z(timezone_t restrict, struct tm *restrict);
-timezone_t tzalloc(char const *);
-void tzfree(timezone_t);
+timezone_t rl_tzalloc(char const *, int *);
+void rl_tzfree(timezone_t);
 # ifdef STD_INSPIRED
-#  if !defined posix2time_z || defined time_tz
-time_t posix2time_z(timezone_t, time_t) ATTRIBUTE_PURE;
-#  endif
-#  if !defined time2posix_z || defined time_tz
-time_t time2posix_z(timezone_t, time_t) ATTRIBUTE_PURE;
-#  endif
+time_t rl_posix2time_z(timezone_t, time_t) ATTRIBUTE_PURE;
+time_t rl_time2posix_z(timezone_t, time_t) ATTRIBUTE_PURE;
 # endif
 #endif
 
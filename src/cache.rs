@@ -0,0 +1,299 @@
+//! Cache of loaded time zones.
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
+use crate::{TimeZone, TzError, ReloadPolicy, Reloaded};
+use crate::reload::{self, Watched};
+use crate::{env, zoneinfo};
+
+/// Capacity of the cache created by [`ZoneCache::default`].
+const DEFAULT_CAPACITY: usize = 32;
+
+struct Entry {
+    zone: Arc<TimeZone>,
+    /// Value of `Inner::tick` when the entry was last returned.
+    last_used: u64,
+    /// The file of the zone, `None` if it was loaded while reloading was off.
+    watched: Option<Watched>,
+    /// The value of [`zoneinfo::generation`] before loading.
+    generation: u64,
+    /// The value of `TZDIR` before loading if the zone was searched for in the zoneinfo
+    /// directories.
+    tzdir: Option<OsString>,
+}
+
+impl Entry {
+    /// Returns `true` if the zone with `name` would be looked up in different directories now.
+    fn is_stale(&self, name: &str) -> bool {
+        self.generation != zoneinfo::generation()
+            || (zoneinfo::is_searched(Some(OsStr::new(name))) && !env::var_eq("TZDIR", self.tzdir.as_deref()))
+    }
+}
+
+struct Inner {
+    zones: HashMap<String, Entry>,
+    capacity: usize,
+    tick: u64,
+    reload: ReloadPolicy,
+}
+
+impl Inner {
+    /// Removes least recently used entries until there are at most `capacity` of them.
+    fn evict(&mut self) {
+        while self.zones.len() > self.capacity {
+            let oldest = self.zones
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(name, _)| name.clone())
+                .expect("the map is not empty");
+            self.zones.remove(&oldest);
+        }
+    }
+}
+
+/// Thread-safe cache of time zones loaded by name.
+///
+/// Looking up a zone that is already cached costs a hash lookup instead of reading and parsing
+/// the file. When the cache is full the least recently used zone is evicted. The zones are
+/// handed out as [`Arc`] so evicting doesn't affect the users of the zone. Finding the least
+/// recently used zone takes time linear in the capacity, so the cache is meant for tens of zones
+/// rather than thousands.
+///
+/// A zone loaded before the [zoneinfo path](crate::set_zoneinfo_path) or `TZDIR` changed is
+/// loaded again from the new location.
+///
+/// Failures are not cached.
+pub struct ZoneCache {
+    inner: Mutex<Inner>,
+}
+
+impl ZoneCache {
+    /// Creates an empty cache holding at most `capacity` zones.
+    ///
+    /// Zero capacity disables caching.
+    pub fn new(capacity: usize) -> Self {
+        ZoneCache {
+            inner: Mutex::new(Inner {
+                zones: HashMap::new(),
+                capacity,
+                tick: 0,
+                reload: ReloadPolicy::Never,
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        // The state is consistent at all times so poisoning doesn't matter.
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns the zone with the given name loading it with [`TimeZone::load`] if it's not
+    /// cached.
+    ///
+    /// The zone is loaded without holding the lock so other threads are not blocked by the file
+    /// system. If the [reload policy](Self::set_reload_policy) says so, the file of a cached zone
+    /// is checked and the zone is loaded again if the file changed.
+    pub fn get(&self, name: &str) -> Result<Arc<TimeZone>, TzError> {
+        let watch = {
+            let mut inner = self.lock();
+            inner.tick += 1;
+            let tick = inner.tick;
+            let policy = inner.reload;
+            let stale = match inner.zones.get(name) {
+                Some(entry) => entry.is_stale(name),
+                None => false,
+            };
+            if stale {
+                inner.zones.remove(name);
+            }
+            if let Some(entry) = inner.zones.get_mut(name) {
+                entry.last_used = tick;
+                let zone = Arc::clone(&entry.zone);
+                let watched = match &mut entry.watched {
+                    Some(watched) => {
+                        if !watched.check_due(policy, Instant::now()) {
+                            return Ok(zone);
+                        }
+                        Some(watched.clone())
+                    },
+                    None if policy == ReloadPolicy::Never => return Ok(zone),
+                    None => None,
+                };
+                drop(inner);
+                return Ok(match watched {
+                    Some(watched) if watched.changed() => self.reload(name, zone),
+                    Some(_) => zone,
+                    None => {
+                        self.watch(name, &zone);
+                        zone
+                    },
+                });
+            }
+            // the file system is not touched unless the zone is cached and checked
+            policy != ReloadPolicy::Never && inner.capacity != 0
+        };
+
+        // read before loading so that changes during loading are noticed
+        let watched = if watch { Some(Watched::new(Some(OsStr::new(name)))) } else { None };
+        let generation = zoneinfo::generation();
+        let tzdir = zoneinfo::tzdir_for(Some(OsStr::new(name)));
+        let zone = Arc::new(TimeZone::load(name)?);
+        let mut inner = self.lock();
+        if inner.capacity == 0 {
+            return Ok(zone);
+        }
+        inner.tick += 1;
+        let last_used = inner.tick;
+        // another thread could've loaded the zone in the meantime, keep only one copy
+        let entry = inner.zones.entry(name.to_owned()).or_insert(Entry { zone, last_used, watched, generation, tzdir });
+        entry.last_used = last_used;
+        let zone = Arc::clone(&entry.zone);
+        inner.evict();
+        Ok(zone)
+    }
+
+    /// Loads the zone again replacing `old` in the cache.
+    ///
+    /// If loading fails `old` is kept and returned, the next check will try again.
+    fn reload(&self, name: &str, old: Arc<TimeZone>) -> Arc<TimeZone> {
+        let watched = Watched::new(Some(OsStr::new(name)));
+        let zone = match TimeZone::load(name) {
+            Ok(zone) => Arc::new(zone),
+            Err(_) => return old,
+        };
+        {
+            let mut inner = self.lock();
+            match inner.zones.get_mut(name) {
+                Some(entry) if Arc::ptr_eq(&entry.zone, &old) => {
+                    entry.zone = Arc::clone(&zone);
+                    entry.watched = Some(watched);
+                },
+                // evicted or reloaded by another thread
+                _ => return zone,
+            }
+        }
+        reload::notify(Reloaded::CachedZone(name));
+        zone
+    }
+
+    /// Starts watching the file of a zone loaded while reloading was off.
+    ///
+    /// Only changes made from now on are noticed.
+    fn watch(&self, name: &str, zone: &Arc<TimeZone>) {
+        let watched = Watched::new(Some(OsStr::new(name)));
+        let mut inner = self.lock();
+        match inner.zones.get_mut(name) {
+            Some(entry) if Arc::ptr_eq(&entry.zone, zone) && entry.watched.is_none() => entry.watched = Some(watched),
+            _ => (),
+        }
+    }
+
+    /// Sets when the files of cached zones are checked for changes.
+    ///
+    /// The default is [`ReloadPolicy::Never`]. The checks happen in [`get`](Self::get) and
+    /// reloaded zones are reported to the hook set by [`set_reload_hook`](crate::set_reload_hook).
+    /// While the policy is [`ReloadPolicy::Never`] the files are not accessed at all, so changes
+    /// to zones cached before setting a different policy are noticed only from their first check
+    /// on.
+    pub fn set_reload_policy(&self, policy: ReloadPolicy) {
+        self.lock().reload = policy;
+    }
+
+    /// Returns the maximum number of cached zones.
+    pub fn capacity(&self) -> usize {
+        self.lock().capacity
+    }
+
+    /// Changes the maximum number of cached zones evicting the least recently used ones if there
+    /// are more.
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut inner = self.lock();
+        inner.capacity = capacity;
+        inner.evict();
+    }
+
+    /// Returns the number of cached zones.
+    pub fn len(&self) -> usize {
+        self.lock().zones.len()
+    }
+
+    /// Returns `true` if no zone is cached.
+    pub fn is_empty(&self) -> bool {
+        self.lock().zones.is_empty()
+    }
+
+    /// Removes all zones from the cache.
+    pub fn clear(&self) {
+        self.lock().zones.clear();
+    }
+}
+
+/// Creates a cache with capacity of 32 zones.
+impl Default for ZoneCache {
+    fn default() -> Self {
+        ZoneCache::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl std::fmt::Debug for ZoneCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let inner = self.lock();
+        f.debug_struct("ZoneCache")
+            .field("zones", &inner.zones.keys())
+            .field("capacity", &inner.capacity)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::ZoneCache;
+
+    #[test]
+    fn lru() {
+        let cache = ZoneCache::new(2);
+        let cet = cache.get("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        assert!(Arc::ptr_eq(&cet, &cache.get("CET-1CEST,M3.5.0,M10.5.0/3").unwrap()));
+        let est = cache.get("EST5EDT,M3.2.0,M11.1.0").unwrap();
+        // CET was used less recently
+        cache.get("<+0330>-3:30").unwrap();
+        assert_eq!(cache.len(), 2);
+        assert!(Arc::ptr_eq(&est, &cache.get("EST5EDT,M3.2.0,M11.1.0").unwrap()));
+        assert!(!Arc::ptr_eq(&cet, &cache.get("CET-1CEST,M3.5.0,M10.5.0/3").unwrap()));
+
+        cache.set_capacity(1);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get("foo bar").is_err());
+        assert_eq!(cache.len(), 1);
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn zoneinfo_path_change() {
+        let _globals = crate::lock_globals();
+        let dir = std::env::temp_dir().join(format!("rl_localtime-cache-{}", std::process::id()));
+        for (name, offset) in &[("a", 3600), ("b", 7200)] {
+            std::fs::create_dir_all(dir.join(name)).unwrap();
+            std::fs::write(dir.join(name).join("Zone"), crate::tzif::fixed(*offset, "ABC")).unwrap();
+        }
+
+        let cache = ZoneCache::new(2);
+        crate::set_zoneinfo_path(&[dir.join("a")]);
+        assert_eq!(cache.get("Zone").unwrap().to_local(0).unwrap().offset(), 3600);
+        crate::set_zoneinfo_path(&[dir.join("b")]);
+        assert_eq!(cache.get("Zone").unwrap().to_local(0).unwrap().offset(), 7200);
+        assert_eq!(cache.len(), 1);
+
+        crate::reset_zoneinfo_path();
+        crate::set_env_source(crate::StaticEnv::new().with("TZDIR", dir.join("a")));
+        assert_eq!(cache.get("Zone").unwrap().to_local(0).unwrap().offset(), 3600);
+        crate::set_env_source(crate::StaticEnv::new().with("TZDIR", dir.join("b")));
+        assert_eq!(cache.get("Zone").unwrap().to_local(0).unwrap().offset(), 7200);
+        crate::reset_env_source();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,426 @@
+//! Typed broken-down time.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+const SECS_PER_DAY: i64 = 86400;
+const DAYS_PER_WEEK: i64 = 7;
+
+/// Largest accepted UT offset in seconds.
+///
+/// This is the same limit the C code uses when parsing TZ strings (`HOURSPERDAY * DAYSPERWEEK - 1`
+/// hours plus some minutes and seconds).
+const MAX_OFFSET: i32 = 7 * 24 * 3600 - 1;
+
+/// Returns the number of days since 1970-01-01 of the given proleptic Gregorian date.
+///
+/// The month is 1-based. This is Howard Hinnant's `days_from_civil`.
+pub(crate) fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = i64::from(month);
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+pub(crate) fn is_leap_year(year: i32) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+pub(crate) fn days_in_month(year: i32, month: u8) -> u8 {
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        _ => panic!("invalid month {}", month),
+    }
+}
+
+/// Day of the week.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Weekday {
+    Sunday,
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+}
+
+impl Weekday {
+    const ALL: [Weekday; 7] = [
+        Weekday::Sunday,
+        Weekday::Monday,
+        Weekday::Tuesday,
+        Weekday::Wednesday,
+        Weekday::Thursday,
+        Weekday::Friday,
+        Weekday::Saturday,
+    ];
+
+    /// Returns the number of days since Sunday (0-6), the same as `tm_wday`.
+    pub fn days_since_sunday(self) -> u8 {
+        self as u8
+    }
+
+    /// Converts the number of days since Sunday (0-6) to weekday.
+    pub fn from_days_since_sunday(days: u8) -> Option<Self> {
+        Self::ALL.get(usize::from(days)).copied()
+    }
+}
+
+/// Maximum length of [`Abbreviation`], the same as `TZ_ABBR_MAX_LEN` in C.
+pub(crate) const ABBR_MAX_LEN: usize = 16;
+
+/// Time zone abbreviation such as `CET` or `+0330`.
+///
+/// This is a copy of the string stored in the time zone so it doesn't borrow anything. The C code
+/// replaces characters other than ASCII alphanumerics and ` :+-._` with `_` and truncates
+/// abbreviations to 16 characters.
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Abbreviation {
+    bytes: [u8; ABBR_MAX_LEN],
+    len: u8,
+}
+
+impl Abbreviation {
+    /// Copies the zero-terminated abbreviation from the buffer filled by C code.
+    pub(crate) fn from_c(buf: &[libc::c_char]) -> Self {
+        let mut bytes = [0; ABBR_MAX_LEN];
+        let mut len = 0;
+        for (dst, src) in bytes.iter_mut().zip(buf).take_while(|(_, src)| **src != 0) {
+            *dst = if (*src as u8).is_ascii() { *src as u8 } else { b'_' };
+            len += 1;
+        }
+        Abbreviation {
+            bytes,
+            len,
+        }
+    }
+
+    /// Returns the abbreviation as a string slice.
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.bytes[..usize::from(self.len)]).expect("the abbreviation is always ASCII")
+    }
+}
+
+impl std::ops::Deref for Abbreviation {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for Abbreviation {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for Abbreviation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Debug for Abbreviation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+/// A field of [`LocalDateTime`] was out of range.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum DateTimeError {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+    Offset,
+}
+
+impl fmt::Display for DateTimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let field = match self {
+            DateTimeError::Year => "year",
+            DateTimeError::Month => "month",
+            DateTimeError::Day => "day",
+            DateTimeError::Hour => "hour",
+            DateTimeError::Minute => "minute",
+            DateTimeError::Second => "second",
+            DateTimeError::Offset => "UT offset",
+        };
+        write!(f, "{} out of range", field)
+    }
+}
+
+impl std::error::Error for DateTimeError {}
+
+/// Validated broken-down calendar time.
+///
+/// This is a typed alternative to `libc::tm`: the year is the actual year, months and days are
+/// 1-based and the DST flag is an `Option` instead of a tri-state integer. The weekday and day of
+/// year are computed from the date so they can never be inconsistent.
+///
+/// When the value is passed to `mktime`-like functions the offset and DST flag are only used as
+/// hints to pick between repeated local times.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct LocalDateTime {
+    year: i32,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+    offset: i32,
+    is_dst: Option<bool>,
+    abbreviation: Option<Abbreviation>,
+}
+
+impl LocalDateTime {
+    /// Creates the date time with zero offset and unknown DST.
+    ///
+    /// `second` may be 60 to represent a leap second.
+    pub fn new(year: i32, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Result<Self, DateTimeError> {
+        if year.checked_sub(crate::TM_YEAR_BASE).is_none() {
+            return Err(DateTimeError::Year);
+        }
+        if !(1..=12).contains(&month) {
+            return Err(DateTimeError::Month);
+        }
+        if day < 1 || day > days_in_month(year, month) {
+            return Err(DateTimeError::Day);
+        }
+        if hour > 23 {
+            return Err(DateTimeError::Hour);
+        }
+        if minute > 59 {
+            return Err(DateTimeError::Minute);
+        }
+        if second > 60 {
+            return Err(DateTimeError::Second);
+        }
+
+        Ok(LocalDateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            offset: 0,
+            is_dst: None,
+            abbreviation: None,
+        })
+    }
+
+    /// Sets the offset from UT in seconds (positive east of Greenwich).
+    pub fn with_offset(mut self, offset: i32) -> Result<Self, DateTimeError> {
+        if !(-MAX_OFFSET..=MAX_OFFSET).contains(&offset) {
+            return Err(DateTimeError::Offset);
+        }
+        self.offset = offset;
+        Ok(self)
+    }
+
+    /// Sets the DST flag, `None` means unknown.
+    pub fn with_dst(mut self, is_dst: Option<bool>) -> Self {
+        self.is_dst = is_dst;
+        self
+    }
+
+    /// Returns the year (e.g. 2022).
+    pub fn year(&self) -> i32 {
+        self.year
+    }
+
+    /// Returns the month (1-12).
+    pub fn month(&self) -> u8 {
+        self.month
+    }
+
+    /// Returns the day of the month (1-31).
+    pub fn day(&self) -> u8 {
+        self.day
+    }
+
+    /// Returns the hour (0-23).
+    pub fn hour(&self) -> u8 {
+        self.hour
+    }
+
+    /// Returns the minute (0-59).
+    pub fn minute(&self) -> u8 {
+        self.minute
+    }
+
+    /// Returns the second (0-60).
+    pub fn second(&self) -> u8 {
+        self.second
+    }
+
+    /// Returns `true` if this is a positive leap second (second 60).
+    ///
+    /// Conversions only return these for zones with leap seconds, e.g. the ones from `right/`.
+    pub fn is_leap_second(&self) -> bool {
+        self.second == 60
+    }
+
+    /// Returns the offset from UT in seconds (positive east of Greenwich).
+    pub fn offset(&self) -> i32 {
+        self.offset
+    }
+
+    /// Returns whether daylight saving time is in effect, `None` if unknown.
+    pub fn is_dst(&self) -> Option<bool> {
+        self.is_dst
+    }
+
+    /// Returns the time zone abbreviation (e.g. `CEST`), `None` if unknown.
+    ///
+    /// This is only known for values returned from conversions.
+    pub fn abbreviation(&self) -> Option<&str> {
+        self.abbreviation.as_ref().map(Abbreviation::as_str)
+    }
+
+    pub(crate) fn with_abbreviation(mut self, abbreviation: Abbreviation) -> Self {
+        self.abbreviation = Some(abbreviation);
+        self
+    }
+
+    /// Returns the day of the week.
+    pub fn weekday(&self) -> Weekday {
+        let days = days_from_civil(self.year.into(), self.month, self.day);
+        // 1970-01-01 was Thursday
+        Weekday::ALL[(days + 4).rem_euclid(DAYS_PER_WEEK) as usize]
+    }
+
+    /// Returns the day of the year (1-366).
+    pub fn day_of_year(&self) -> u16 {
+        let days = days_from_civil(self.year.into(), self.month, self.day);
+        (days - days_from_civil(self.year.into(), 1, 1) + 1) as u16
+    }
+
+    /// Returns the number of seconds since 1970-01-01 00:00:00 ignoring the offset.
+    pub(crate) fn local_seconds(&self) -> i64 {
+        days_from_civil(self.year.into(), self.month, self.day) * SECS_PER_DAY
+            + i64::from(self.hour) * 3600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second)
+    }
+}
+
+/// Formats the value as ISO 8601 date and time with offset (e.g. `2022-03-27T03:00:00+02:00`).
+impl fmt::Display for LocalDateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if (0..=9999).contains(&self.year) {
+            write!(f, "{:04}", self.year)?;
+        } else {
+            write!(f, "{:+05}", self.year)?;
+        }
+        write!(f, "-{:02}-{:02}T{:02}:{:02}:{:02}", self.month, self.day, self.hour, self.minute, self.second)?;
+        let sign = if self.offset < 0 { '-' } else { '+' };
+        let offset = self.offset.abs();
+        write!(f, "{}{:02}:{:02}", sign, offset / 3600, offset / 60 % 60)?;
+        if offset % 60 != 0 {
+            write!(f, ":{:02}", offset % 60)?;
+        }
+        Ok(())
+    }
+}
+
+/// The abbreviation is not converted and `tm_zone` is null.
+impl From<LocalDateTime> for libc::tm {
+    fn from(value: LocalDateTime) -> Self {
+        // zeroed to initialize platform-specific fields; tm_zone is null
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        tm.tm_year = value.year - crate::TM_YEAR_BASE;
+        tm.tm_mon = i32::from(value.month) - 1;
+        tm.tm_mday = value.day.into();
+        tm.tm_hour = value.hour.into();
+        tm.tm_min = value.minute.into();
+        tm.tm_sec = value.second.into();
+        tm.tm_wday = value.weekday().days_since_sunday().into();
+        tm.tm_yday = i32::from(value.day_of_year()) - 1;
+        tm.tm_isdst = match value.is_dst {
+            None => -1,
+            Some(false) => 0,
+            Some(true) => 1,
+        };
+        tm.tm_gmtoff = value.offset.into();
+        tm
+    }
+}
+
+/// Validates the fields of `tm`.
+///
+/// `tm_wday` and `tm_yday` are ignored since they are computed from the date. `tm_zone` is ignored
+/// too because it has no defined lifetime so the abbreviation is unknown.
+impl TryFrom<libc::tm> for LocalDateTime {
+    type Error = DateTimeError;
+
+    fn try_from(tm: libc::tm) -> Result<Self, Self::Error> {
+        fn field(value: libc::c_int, base: libc::c_int, error: DateTimeError) -> Result<u8, DateTimeError> {
+            value.checked_add(base).and_then(|value| u8::try_from(value).ok()).ok_or(error)
+        }
+
+        let year = tm.tm_year.checked_add(crate::TM_YEAR_BASE).ok_or(DateTimeError::Year)?;
+        let offset = i32::try_from(tm.tm_gmtoff).map_err(|_| DateTimeError::Offset)?;
+        let is_dst = match tm.tm_isdst {
+            i32::MIN..=-1 => None,
+            0 => Some(false),
+            _ => Some(true),
+        };
+        LocalDateTime::new(
+            year,
+            field(tm.tm_mon, 1, DateTimeError::Month)?,
+            field(tm.tm_mday, 0, DateTimeError::Day)?,
+            field(tm.tm_hour, 0, DateTimeError::Hour)?,
+            field(tm.tm_min, 0, DateTimeError::Minute)?,
+            field(tm.tm_sec, 0, DateTimeError::Second)?,
+        )?
+        .with_offset(offset)
+        .map(|value| value.with_dst(is_dst))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use super::{LocalDateTime, DateTimeError, Weekday};
+
+    #[test]
+    fn tm_round_trip() {
+        let time = LocalDateTime::new(2024, 2, 29, 23, 59, 60)
+            .unwrap()
+            .with_offset(-(3 * 3600 + 30 * 60))
+            .unwrap()
+            .with_dst(Some(true));
+        assert_eq!(time.weekday(), Weekday::Thursday);
+        assert_eq!(time.day_of_year(), 60);
+        assert_eq!(time.to_string(), "2024-02-29T23:59:60-03:30");
+
+        let tm = libc::tm::from(time);
+        assert_eq!(tm.tm_year, 124);
+        assert_eq!(tm.tm_mon, 1);
+        assert_eq!(tm.tm_wday, 4);
+        assert_eq!(tm.tm_yday, 59);
+        assert_eq!(tm.tm_isdst, 1);
+        assert_eq!(LocalDateTime::try_from(tm), Ok(time));
+    }
+
+    #[test]
+    fn validation() {
+        assert_eq!(LocalDateTime::new(2023, 2, 29, 0, 0, 0), Err(DateTimeError::Day));
+        assert_eq!(LocalDateTime::new(2023, 13, 1, 0, 0, 0), Err(DateTimeError::Month));
+        assert_eq!(LocalDateTime::new(2023, 1, 1, 24, 0, 0), Err(DateTimeError::Hour));
+        assert_eq!(LocalDateTime::new(i32::MIN, 1, 1, 0, 0, 0), Err(DateTimeError::Year));
+    }
+}
@@ -0,0 +1,139 @@
+//! Source of the environment variables consulted by this crate.
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::sync::{Arc, RwLock, PoisonError};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Provides the values of `TZ` and `TZDIR`.
+///
+/// By default they are read from the process environment. Installing a different source with
+/// [`set_env_source`] allows controlling the local time zone without calling
+/// `std::env::set_var`, e.g. per tenant or from a configuration file.
+///
+/// The values may change over time, the local time zone is loaded again on the next conversion
+/// after `TZ` or `TZDIR` changed.
+///
+/// This is implemented for closures so `set_env_source(|name| ...)` works.
+pub trait EnvSource: Send + Sync {
+    /// Returns the value of the variable with the given name or `None` if it's not set.
+    fn var_os(&self, name: &str) -> Option<OsString>;
+
+    /// Returns `true` if the variable has the given value, `None` meaning it's not set.
+    ///
+    /// This is called on every conversion using the local time zone, the value is only copied
+    /// when the zone is loaded. The default calls [`var_os`](Self::var_os), sources that can
+    /// compare without copying the value should override it.
+    fn var_eq(&self, name: &str, value: Option<&OsStr>) -> bool {
+        self.var_os(name).as_deref() == value
+    }
+}
+
+impl<F: Fn(&str) -> Option<OsString> + Send + Sync> EnvSource for F {
+    fn var_os(&self, name: &str) -> Option<OsString> {
+        self(name)
+    }
+}
+
+/// Reads the variables from the process environment using `std::env::var_os` (the default).
+///
+/// std copies the value while holding its environment lock and there's no other way to read it
+/// without racing with `std::env::set_var`, so comparing a variable that is set allocates.
+/// Install a [`StaticEnv`] or a source implementing [`EnvSource::var_eq`] if that matters.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ProcessEnv;
+
+impl EnvSource for ProcessEnv {
+    fn var_os(&self, name: &str) -> Option<OsString> {
+        std::env::var_os(name)
+    }
+}
+
+/// Fixed set of variables, the ones not set are treated as unset.
+#[derive(Debug, Clone, Default)]
+pub struct StaticEnv {
+    vars: HashMap<String, OsString>,
+}
+
+impl StaticEnv {
+    /// Creates an environment with no variables set.
+    pub fn new() -> Self {
+        StaticEnv::default()
+    }
+
+    /// Sets the variable returning the modified environment.
+    pub fn with<V: AsRef<OsStr>>(mut self, name: &str, value: V) -> Self {
+        self.set(name, value);
+        self
+    }
+
+    /// Sets the variable.
+    pub fn set<V: AsRef<OsStr>>(&mut self, name: &str, value: V) {
+        self.vars.insert(name.to_owned(), value.as_ref().to_owned());
+    }
+
+    /// Unsets the variable.
+    pub fn remove(&mut self, name: &str) {
+        self.vars.remove(name);
+    }
+}
+
+impl EnvSource for StaticEnv {
+    fn var_os(&self, name: &str) -> Option<OsString> {
+        self.vars.get(name).cloned()
+    }
+
+    fn var_eq(&self, name: &str, value: Option<&OsStr>) -> bool {
+        self.vars.get(name).map(OsString::as_os_str) == value
+    }
+}
+
+/// Avoids locking on every conversion if the default source is used.
+static CUSTOM: AtomicBool = AtomicBool::new(false);
+
+static SOURCE: RwLock<Option<Arc<dyn EnvSource>>> = RwLock::new(None);
+
+/// Sets the source of `TZ` and `TZDIR` for the whole process.
+///
+/// The local time zone is loaded again on the next conversion.
+pub fn set_env_source<S: EnvSource + 'static>(source: S) {
+    *SOURCE.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(source));
+    CUSTOM.store(true, Ordering::Release);
+    crate::local::invalidate();
+}
+
+/// Goes back to reading the process environment.
+pub fn reset_env_source() {
+    CUSTOM.store(false, Ordering::Release);
+    *SOURCE.write().unwrap_or_else(PoisonError::into_inner) = None;
+    crate::local::invalidate();
+}
+
+/// Returns `true` if the variable from the current source has the given value.
+///
+/// Unlike [`var_os`] this doesn't allocate unless the source has to copy the value. Sadly, std
+/// can't lend the value of a variable set in the process environment, so that is copied.
+pub(crate) fn var_eq(name: &str, value: Option<&OsStr>) -> bool {
+    if !CUSTOM.load(Ordering::Acquire) {
+        return std::env::var_os(name).as_deref() == value;
+    }
+    // cloned so that the source can replace itself
+    let source = SOURCE.read().unwrap_or_else(PoisonError::into_inner).clone();
+    match source {
+        Some(source) => source.var_eq(name, value),
+        None => std::env::var_os(name).as_deref() == value,
+    }
+}
+
+/// Returns the value of the variable from the current source.
+pub(crate) fn var_os(name: &str) -> Option<OsString> {
+    if !CUSTOM.load(Ordering::Acquire) {
+        return std::env::var_os(name);
+    }
+    // cloned so that the source can replace itself
+    let source = SOURCE.read().unwrap_or_else(PoisonError::into_inner).clone();
+    match source {
+        Some(source) => source.var_os(name),
+        None => std::env::var_os(name),
+    }
+}
@@ -0,0 +1,72 @@
+//! Error type returned by conversions.
+
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use crate::{DateTimeError, TzifError, LocalZoneError};
+
+/// Error returned when a conversion or loading a time zone fails.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum TzError {
+    /// The result doesn't fit into `time_t` or the broken-down time.
+    ///
+    /// This is `EOVERFLOW` in the C code.
+    Overflow,
+    /// The time zone could not be loaded.
+    ///
+    /// Contains the errno value from the C code.
+    ZoneLoad(io::Error),
+    /// The TZif data is invalid.
+    Tzif(TzifError),
+    /// The calendar time is invalid.
+    InvalidDateTime(DateTimeError),
+    /// The local time is repeated and the policy was to reject it.
+    Ambiguous,
+    /// The local time was skipped and the policy was to reject it.
+    Nonexistent,
+    /// The time is past the expiration of the leap second table so leap seconds may be missing.
+    Expired,
+    /// The local time zone could not be loaded and [strict mode](crate::set_strict_local_zone) is
+    /// on.
+    LocalZone(Arc<LocalZoneError>),
+}
+
+impl fmt::Display for TzError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TzError::Overflow => write!(f, "time value out of range"),
+            TzError::ZoneLoad(_) => write!(f, "failed to load the time zone"),
+            TzError::Tzif(_) => write!(f, "invalid TZif data"),
+            TzError::InvalidDateTime(_) => write!(f, "invalid date time"),
+            TzError::Ambiguous => write!(f, "the local time is ambiguous"),
+            TzError::Nonexistent => write!(f, "the local time doesn't exist"),
+            TzError::Expired => write!(f, "the leap second table expired"),
+            TzError::LocalZone(_) => write!(f, "failed to load the local time zone"),
+        }
+    }
+}
+
+impl std::error::Error for TzError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TzError::Overflow | TzError::Ambiguous | TzError::Nonexistent | TzError::Expired => None,
+            TzError::ZoneLoad(error) => Some(error),
+            TzError::Tzif(error) => Some(error),
+            TzError::InvalidDateTime(error) => Some(error),
+            TzError::LocalZone(error) => Some(&**error),
+        }
+    }
+}
+
+impl From<DateTimeError> for TzError {
+    fn from(value: DateTimeError) -> Self {
+        TzError::InvalidDateTime(value)
+    }
+}
+
+impl From<TzifError> for TzError {
+    fn from(value: TzifError) -> Self {
+        TzError::Tzif(value)
+    }
+}
@@ -0,0 +1,120 @@
+//! Declarations of the functions implemented in `c_lib/localtime.c`.
+
+use libc::time_t;
+use libc::c_char;
+use libc::c_int;
+use libc::c_long;
+
+/// Opaque parsed time zone (`struct state` in C).
+///
+/// The C code never modifies the state after it was initialized so it can be shared between
+/// threads freely.
+#[repr(C)]
+pub(crate) struct State {
+    _private: [u8; 0],
+}
+
+extern "C" {
+    pub(crate) fn rl_timegm(tm: *mut libc::tm, out: *mut time_t) -> c_int;
+
+    pub(crate) fn rl_tzalloc(name: *const c_char, origin: *mut c_int) -> *mut State;
+    pub(crate) fn rl_tzalloc_tzif(buf: *const c_char, len: usize, section: *mut c_int) -> *mut State;
+    pub(crate) fn rl_tzfree(sp: *mut State);
+    pub(crate) fn rl_localtime_rz(sp: *const State, sec: *const time_t, out: *mut libc::tm, abbr: *mut c_char, abbr_size: usize) -> *mut libc::tm;
+    pub(crate) fn rl_next_transition(sp: *const State, time: *mut time_t, before: *mut c_int, after: *mut c_int) -> bool;
+    pub(crate) fn rl_zone_type(sp: *const State, i: c_int, gmtoff: *mut c_long, isdst: *mut bool, abbr: *mut c_char, abbr_size: usize);
+    pub(crate) fn rl_mktime_z(sp: *const State, tm: *mut libc::tm, out: *mut time_t) -> c_int;
+    pub(crate) fn rl_time2posix_z(sp: *const State, t: time_t) -> time_t;
+    pub(crate) fn rl_posix2time_z(sp: *const State, t: time_t) -> time_t;
+    pub(crate) fn rl_leap_second(sp: *const State, i: c_int, trans: *mut time_t, corr: *mut i64) -> bool;
+    pub(crate) fn rl_tzalloc_leaps(sp: *const State, trans: *const time_t, corr: *const i64, leapcnt: c_int) -> *mut State;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::time::SystemTime;
+    use std::process::Command;
+
+    fn modified(path: &Path) -> SystemTime {
+        path.metadata()
+            .and_then(|meta| meta.modified())
+            .unwrap_or_else(|error| panic!("failed to get mtime of {}: {}", path.display(), error))
+    }
+
+    /// Returns the time the most recently changed file in `path` was modified.
+    fn last_change(path: &Path) -> SystemTime {
+        if !path.is_dir() {
+            return modified(path);
+        }
+        path.read_dir()
+            .expect("failed to read source directory")
+            .map(|entry| last_change(&entry.expect("failed to read source directory").path()))
+            .max()
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+    }
+
+    /// Finds the rlib of this crate that `cargo test` builds next to the test executable.
+    ///
+    /// The rlib is what downstream crates link, so it contains both the C library and the
+    /// exported Rust functions. If there are several of them (e.g. after changing features) the
+    /// most recently built one is used.
+    fn rlib() -> PathBuf {
+        let exe = std::env::current_exe().expect("failed to get test executable");
+        let deps = exe.parent().expect("test executable has no parent directory");
+        let prefix = concat!("lib", env!("CARGO_CRATE_NAME"), "-");
+        let rlib = deps.read_dir()
+            .expect("failed to read deps directory")
+            .map(|entry| entry.expect("failed to read deps directory").path())
+            .filter(|path| {
+                let name = path.file_name().unwrap().to_string_lossy();
+                name.starts_with(prefix) && name.ends_with(".rlib")
+            })
+            .max_by_key(|path| modified(path))
+            .unwrap_or_else(|| panic!("no rlib in {}, run the whole `cargo test` so that it gets built", deps.display()));
+
+        // cargo doesn't rebuild the rlib when running filtered tests
+        let sources = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let changed = ["src", "c_lib", "build.rs"].iter().map(|path| last_change(&sources.join(path))).max().unwrap();
+        assert!(modified(&rlib) >= changed, "{} is outdated, run the whole `cargo test` so that it gets rebuilt", rlib.display());
+        rlib
+    }
+
+    /// Lists global symbols of the compiled library using `nm`.
+    fn symbols(kind: &str) -> Vec<String> {
+        let output = Command::new("nm")
+            .args(["-g", "-P", kind])
+            .arg(rlib())
+            .output()
+            .expect("failed to run nm");
+        assert!(output.status.success(), "nm failed: {}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            // the first line of an archive member is its name ending with ':'
+            .filter(|line| !line.ends_with(':'))
+            .filter_map(|line| line.split_whitespace().next())
+            // mangled Rust symbols can't clash with C ones
+            .filter(|symbol| !symbol.starts_with("_ZN") && !symbol.starts_with("_R"))
+            .map(ToOwned::to_owned)
+            .collect()
+    }
+
+    #[test]
+    fn symbols_dont_clash_with_libc() {
+        let system_time = ["tzname", "timezone", "daylight", "tzset", "localtime", "localtime_r", "mktime", "asctime", "asctime_r", "gmtime", "gmtime_r", "timegm", "ctime", "ctime_r", "offtime", "timelocal", "time2posix", "posix2time", "tzalloc", "tzfree", "localtime_rz", "mktime_z"];
+
+        let defined = symbols("--defined-only");
+        assert!(defined.iter().any(|symbol| symbol == "rl_tzalloc"), "C library missing from the rlib");
+        for symbol in &defined {
+            // `rust_` functions are the callbacks the C code uses, `DW.ref.` are unwinding helpers
+            let prefixed = symbol.starts_with("rl_") || symbol.starts_with("rust_") || symbol.starts_with("DW.ref.");
+            assert!(prefixed, "unprefixed symbol {}", symbol);
+            assert!(!system_time.contains(&&**symbol), "exports system {}", symbol);
+        }
+
+        for symbol in symbols("--undefined-only") {
+            assert!(!system_time.contains(&&*symbol), "uses system {}", symbol);
+        }
+    }
+}
@@ -0,0 +1,478 @@
+//! Leap seconds stored in time zones and leap second tables.
+//!
+//! Zones from the `right/` directory of the tz database count leap seconds in their time values,
+//! so their "Unix time" is not POSIX time. The other zones have no leap seconds and the
+//! conversions below don't change anything for them. A [`LeapTable`] parsed from the files
+//! distributed with the tz database can be attached to any zone using
+//! [`TimeZone::with_leap_table`].
+
+use std::convert::TryFrom;
+use std::fmt;
+use libc::time_t;
+use crate::{ffi, LocalDateTime, TimeZone};
+
+/// Seconds between the NTP epoch (1900-01-01) and the Unix epoch.
+const NTP_UNIX_OFFSET: i64 = 2_208_988_800;
+
+/// TAI - UTC before the first leap second as assumed by the tz database.
+const INITIAL_TAI_UTC: i64 = 10;
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Leap second record of a time zone.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct LeapSecond {
+    at: time_t,
+    correction: i64,
+}
+
+impl LeapSecond {
+    /// Returns the time at which the correction takes effect.
+    ///
+    /// The time counts leap seconds, like the times used with the zone.
+    pub fn at(&self) -> time_t {
+        self.at
+    }
+
+    /// Returns the total number of leap seconds inserted (or removed, if negative) since the
+    /// epoch, including this one.
+    pub fn correction(&self) -> i64 {
+        self.correction
+    }
+}
+
+/// Iterator over the leap seconds of a time zone returned from [`TimeZone::leap_seconds`].
+///
+/// If the zone records when its leap seconds expire the last item has the same correction as the
+/// previous one and its time is the expiration, like in TZif files.
+#[derive(Debug, Clone)]
+pub struct LeapSeconds<'a> {
+    zone: &'a TimeZone,
+    next: libc::c_int,
+}
+
+impl<'a> LeapSeconds<'a> {
+    pub(crate) fn new(zone: &'a TimeZone) -> Self {
+        LeapSeconds {
+            zone,
+            next: 0,
+        }
+    }
+}
+
+impl Iterator for LeapSeconds<'_> {
+    type Item = LeapSecond;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut at = 0;
+        let mut correction = 0;
+        if !unsafe { ffi::rl_leap_second(self.zone.state(), self.next, &mut at, &mut correction) } {
+            return None;
+        }
+        self.next += 1;
+        Some(LeapSecond { at, correction })
+    }
+}
+
+/// Leap seconds parsed from a `leap-seconds.list` or `leapseconds` file.
+///
+/// The leap seconds are stored the same way as in TZif files: each one is the time counting the
+/// previous leap seconds at which the total correction changes.
+///
+/// The table converts between POSIX time, TAI and GPS time using [`convert`](Self::convert). To
+/// use the leap seconds of a `right/` zone get its table with [`TimeZone::leap_table`].
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct LeapTable {
+    leap_seconds: Vec<LeapSecond>,
+    initial_tai_utc: i64,
+    updated: Option<time_t>,
+    expires: Option<time_t>,
+}
+
+impl LeapTable {
+    /// Parses the `leap-seconds.list` file published by IERS and NIST.
+    ///
+    /// The first entry gives the initial difference between TAI and UTC, each of the following
+    /// ones adds or removes a leap second. The hash in the `#h` line is verified if present.
+    pub fn parse_leap_seconds_list(text: &str) -> Result<Self, LeapTableError> {
+        let mut updated = None;
+        let mut expires = None;
+        let mut hash = None;
+        // the hash covers the numbers in the file except the hash itself
+        let mut hashed = String::new();
+        // line number, POSIX time and TAI - UTC
+        let mut entries = Vec::new();
+        for (line, text) in (1..).zip(text.lines()) {
+            if let Some(rest) = text.strip_prefix("#$") {
+                let time = rest.trim();
+                updated = Some(ntp_to_posix(time, line)?);
+                hashed.push_str(time);
+            } else if let Some(rest) = text.strip_prefix("#@") {
+                let time = rest.trim();
+                expires = Some(ntp_to_posix(time, line)?);
+                hashed.push_str(time);
+            } else if let Some(rest) = text.strip_prefix("#h") {
+                let mut words = [0; 5];
+                let mut fields = rest.split_whitespace();
+                for word in &mut words {
+                    let field = fields.next().ok_or(LeapTableError { line, expected: "five hash words" })?;
+                    *word = u32::from_str_radix(field, 16).map_err(|_| LeapTableError { line, expected: "hexadecimal number" })?;
+                }
+                if fields.next().is_some() {
+                    return Err(LeapTableError { line, expected: "end of line" });
+                }
+                hash = Some((line, words));
+            } else {
+                let mut fields = text.split('#').next().unwrap_or_default().split_whitespace();
+                let time = match fields.next() {
+                    Some(time) => time,
+                    None => continue,
+                };
+                let difference = fields.next().ok_or(LeapTableError { line, expected: "TAI - UTC" })?;
+                if fields.next().is_some() {
+                    return Err(LeapTableError { line, expected: "end of line" });
+                }
+                hashed.push_str(time);
+                hashed.push_str(difference);
+                let difference = difference.parse::<i64>().map_err(|_| LeapTableError { line, expected: "number" })?;
+                entries.push((line, ntp_to_posix(time, line)?, difference));
+            }
+        }
+        if let Some((line, hash)) = hash {
+            if crate::sha1::sha1(hashed.as_bytes()) != hash {
+                return Err(LeapTableError { line, expected: "hash of the data" });
+            }
+        }
+
+        let mut builder = Builder::default();
+        for pair in entries.windows(2) {
+            let (_, _, previous) = pair[0];
+            let (line, time, difference) = pair[1];
+            // a positive leap second is inserted before midnight, a negative one is the last
+            // second before midnight
+            let (time, positive) = match difference - previous {
+                1 => (time, true),
+                -1 => (time.checked_sub(1).ok_or(LeapTableError { line, expected: "time in range" })?, false),
+                _ => return Err(LeapTableError { line, expected: "change of TAI - UTC by one second" }),
+            };
+            builder.add(time, positive, line)?;
+        }
+        let initial_tai_utc = entries.first().map_or(INITIAL_TAI_UTC, |&(_, _, difference)| difference);
+        Ok(builder.finish(initial_tai_utc, updated, expires))
+    }
+
+    /// Parses the `leapseconds` file of the tz database.
+    ///
+    /// The file doesn't contain the initial difference between TAI and UTC so it's assumed to be
+    /// 10 seconds.
+    ///
+    /// These are the `Leap` lines in the format accepted by `zic` and the expiration given either
+    /// by an `Expires` line or by the commented out `#Expires` line. The time of the last update
+    /// is taken from the `#updated` comment if present.
+    pub fn parse_leapseconds(text: &str) -> Result<Self, LeapTableError> {
+        let mut updated = None;
+        let mut expires = None;
+        let mut builder = Builder::default();
+        for (line, text) in (1..).zip(text.lines()) {
+            let text = if text.starts_with("#Expires") { &text[1..] } else { text };
+            if let Some(rest) = text.strip_prefix("#updated") {
+                let time = rest.split_whitespace().next().ok_or(LeapTableError { line, expected: "time" })?;
+                updated = Some(to_time_t(time.parse().map_err(|_| LeapTableError { line, expected: "number" })?, line)?);
+                continue;
+            }
+            let mut fields = text.split('#').next().unwrap_or_default().split_whitespace();
+            match fields.next() {
+                None => continue,
+                Some("Leap") => {
+                    let time = date_time(&mut fields, line)?;
+                    let positive = match fields.next() {
+                        Some("+") => true,
+                        Some("-") => false,
+                        _ => return Err(LeapTableError { line, expected: "'+' or '-'" }),
+                    };
+                    // rolling leap seconds happen at local time which isn't supported
+                    if fields.next() != Some("S") {
+                        return Err(LeapTableError { line, expected: "'S'" });
+                    }
+                    if fields.next().is_some() {
+                        return Err(LeapTableError { line, expected: "end of line" });
+                    }
+                    builder.add(time, positive, line)?;
+                },
+                Some("Expires") => {
+                    expires = Some(date_time(&mut fields, line)?);
+                    if fields.next().is_some() {
+                        return Err(LeapTableError { line, expected: "end of line" });
+                    }
+                },
+                Some(_) => return Err(LeapTableError { line, expected: "'Leap' or 'Expires'" }),
+            }
+        }
+        Ok(builder.finish(INITIAL_TAI_UTC, updated, expires))
+    }
+
+    /// Returns the leap seconds of the zone and the time they expire.
+    pub(crate) fn from_zone(zone: &TimeZone) -> Self {
+        let mut leap_seconds = zone.leap_seconds().collect::<Vec<_>>();
+        let previous = leap_seconds.len().checked_sub(2).map_or(0, |i| leap_seconds[i].correction);
+        let expires = match leap_seconds.last() {
+            Some(last) if last.correction == previous => {
+                let expires = last.at.checked_sub(last.correction as time_t);
+                leap_seconds.pop();
+                expires
+            },
+            _ => None,
+        };
+        LeapTable {
+            leap_seconds,
+            initial_tai_utc: INITIAL_TAI_UTC,
+            updated: None,
+            expires,
+        }
+    }
+
+    /// Returns the leap seconds in the order they happened.
+    ///
+    /// Unlike [`TimeZone::leap_seconds`] this never contains the expiration.
+    pub fn leap_seconds(&self) -> &[LeapSecond] {
+        &self.leap_seconds
+    }
+
+    /// Returns TAI - UTC in seconds before the first leap second.
+    ///
+    /// This is the first entry of `leap-seconds.list`. The `leapseconds` file and zones don't
+    /// record it so tables created from them use 10 seconds like the tz database.
+    pub fn initial_tai_utc(&self) -> i64 {
+        self.initial_tai_utc
+    }
+
+    /// Returns the POSIX time of the last update of the table if known.
+    pub fn updated(&self) -> Option<time_t> {
+        self.updated
+    }
+
+    /// Returns the POSIX time after which the table may miss leap seconds if known.
+    pub fn expires(&self) -> Option<time_t> {
+        self.expires
+    }
+}
+
+/// Accumulates leap seconds the way `zic` does.
+#[derive(Default)]
+struct Builder {
+    leap_seconds: Vec<LeapSecond>,
+    correction: i64,
+}
+
+impl Builder {
+    /// Adds the leap second happening at POSIX `time`.
+    fn add(&mut self, time: i64, positive: bool, line: usize) -> Result<(), LeapTableError> {
+        let at = time.checked_add(self.correction).ok_or(LeapTableError { line, expected: "time in range" })?;
+        let at = to_time_t(at, line)?;
+        match self.leap_seconds.last() {
+            Some(last) if last.at >= at => return Err(LeapTableError { line, expected: "time after the previous leap second" }),
+            _ => (),
+        }
+        self.correction += if positive { 1 } else { -1 };
+        self.leap_seconds.push(LeapSecond { at, correction: self.correction });
+        Ok(())
+    }
+
+    fn finish(self, initial_tai_utc: i64, updated: Option<time_t>, expires: Option<time_t>) -> LeapTable {
+        LeapTable {
+            leap_seconds: self.leap_seconds,
+            initial_tai_utc,
+            updated,
+            expires,
+        }
+    }
+}
+
+#[allow(clippy::useless_conversion)]
+fn to_time_t(time: i64, line: usize) -> Result<time_t, LeapTableError> {
+    time_t::try_from(time).map_err(|_| LeapTableError { line, expected: "time in range" })
+}
+
+/// Parses an NTP timestamp returning the POSIX time.
+fn ntp_to_posix(time: &str, line: usize) -> Result<i64, LeapTableError> {
+    let time = time.parse::<i64>().map_err(|_| LeapTableError { line, expected: "NTP timestamp" })?;
+    time.checked_sub(NTP_UNIX_OFFSET).ok_or(LeapTableError { line, expected: "time in range" })
+}
+
+/// Parses `YEAR MONTH DAY HH:MM:SS` returning the POSIX time.
+fn date_time<'a, I: Iterator<Item = &'a str>>(fields: &mut I, line: usize) -> Result<i64, LeapTableError> {
+    let error = |expected| LeapTableError { line, expected };
+    let year = fields.next().and_then(|year| year.parse().ok()).ok_or_else(|| error("year"))?;
+    let month = fields.next()
+        .and_then(|month| MONTHS.iter().position(|name| name.eq_ignore_ascii_case(month)))
+        .ok_or_else(|| error("month name"))?;
+    let day = fields.next().and_then(|day| day.parse().ok()).ok_or_else(|| error("day"))?;
+    let mut time = fields.next().ok_or_else(|| error("time"))?.split(':').map(str::parse::<u8>);
+    let (hour, minute, second) = match (time.next(), time.next(), time.next(), time.next()) {
+        (Some(Ok(hour)), Some(Ok(minute)), Some(Ok(second)), None) => (hour, minute, second),
+        _ => return Err(error("hh:mm:ss")),
+    };
+    let time = LocalDateTime::new(year, month as u8 + 1, day, hour, minute, second).map_err(|_| error("valid date"))?;
+    Ok(time.local_seconds())
+}
+
+/// Error returned when parsing an invalid leap second table.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct LeapTableError {
+    line: usize,
+    expected: &'static str,
+}
+
+impl LeapTableError {
+    /// Returns the line number (starting at 1) at which parsing failed.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl fmt::Display for LeapTableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid leap second table: expected {} on line {}", self.expected, self.line)
+    }
+}
+
+impl std::error::Error for LeapTableError {}
+
+/// Returns `true` if adding or subtracting any correction of the zone to `t` can't overflow.
+///
+/// The C code doesn't check it. The extra second covers the adjustments of `posix2time_z`.
+pub(crate) fn can_correct(zone: &TimeZone, t: time_t) -> bool {
+    // corrections are stored in 32 bits in TZif
+    const SAFE: i64 = 1 << 33;
+    #[allow(clippy::useless_conversion)]
+    let (lowest, highest, t64) = (i64::from(time_t::MIN), i64::from(time_t::MAX), i64::from(t));
+    if t64 >= lowest.saturating_add(SAFE) && t64 <= highest.saturating_sub(SAFE) {
+        return true;
+    }
+    let max = match zone.leap_seconds().map(|leap| leap.correction().unsigned_abs()).max() {
+        Some(max) => max + 1,
+        // nothing is corrected
+        None => return true,
+    };
+    let max = match time_t::try_from(max) {
+        Ok(max) => max,
+        Err(_) => return false,
+    };
+    t.checked_add(max).is_some() && t.checked_sub(max).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LeapTable;
+    use crate::{Disambiguation, LocalDateTime, LocalResult, TimeZone, TzError};
+
+    const LEAP_SECONDS_LIST: &str = "\
+#\tFile expires on 28 June 2026
+#$\t3960835200
+#@\t3991593600
+#
+2272060800\t10\t# 1 Jan 1972
+2287785600\t11\t# 1 Jul 1972
+2303683200\t12\t# 1 Jan 1973
+#
+#h\t2bb8744 5934785 7040be45 616b5dfe 6348ed4b
+";
+
+    const LEAPSECONDS: &str = "\
+# Leap\tYEAR\tMONTH\tDAY\tHH:MM:SS\tCORR\tR/S
+Leap\t1972\tJun\t30\t23:59:60\t+\tS
+Leap\t1972\tDec\t31\t23:59:60\t+\tS
+
+#Expires 2026\tJun\t28\t00:00:00
+#updated 1751846400 (2025-07-07 00:00:00 UTC)
+";
+
+    #[test]
+    fn posix_conversions() {
+        // 1972-07-01 and 1973-01-01 00:00:00 UTC counting the leap seconds before them
+        let data = crate::tzif::fixed_with_leap_seconds(0, "UTC", &[(78796800, 1), (94694401, 2)]);
+        let zone = TimeZone::from_tzif(&data).unwrap();
+        let leaps = zone.leap_seconds().map(|leap| (leap.at(), leap.correction())).collect::<Vec<_>>();
+        assert_eq!(leaps, [(78796800, 1), (94694401, 2)]);
+
+        assert_eq!(zone.to_posix(78796799).unwrap(), 78796799);
+        // 23:59:60 has the same POSIX time as 23:59:59
+        assert_eq!(zone.to_posix(78796800).unwrap(), 78796799);
+        assert_eq!(zone.to_posix(78796801).unwrap(), 78796800);
+        assert_eq!(zone.to_posix(94694403).unwrap(), 94694401);
+        assert_eq!(zone.from_posix(78796799).unwrap(), 78796799);
+        assert_eq!(zone.from_posix(78796800).unwrap(), 78796801);
+        assert_eq!(zone.from_posix(94694401).unwrap(), 94694403);
+        assert!(zone.to_posix(libc::time_t::MIN).is_err());
+
+        let utc = TimeZone::utc().unwrap();
+        assert_eq!(utc.leap_seconds().count(), 0);
+        assert_eq!(utc.to_posix(libc::time_t::MIN).unwrap(), libc::time_t::MIN);
+        assert_eq!(utc.from_posix(94694401).unwrap(), 94694401);
+    }
+
+    #[test]
+    fn second_60() {
+        let data = crate::tzif::fixed_with_leap_seconds(3600, "CET", &[(78796800, 1)]);
+        let zone = TimeZone::from_tzif(&data).unwrap();
+        let leap = zone.to_local(78796800).unwrap();
+        assert!(leap.is_leap_second());
+        assert_eq!(leap.to_string(), "1972-07-01T00:59:60+01:00");
+        assert_eq!(zone.to_local(78796801).unwrap().to_string(), "1972-07-01T01:00:00+01:00");
+        assert_eq!(zone.from_local(leap).unwrap(), 78796800);
+        assert_eq!(zone.resolve_local(leap).unwrap(), LocalResult::Single(78796800));
+        let next = LocalDateTime::new(1972, 7, 1, 1, 0, 0).unwrap();
+        assert_eq!(zone.resolve_local(next).unwrap(), LocalResult::Single(78796801));
+        let before = LocalDateTime::new(1972, 7, 1, 0, 59, 59).unwrap();
+        assert_eq!(zone.resolve_local(before).unwrap(), LocalResult::Single(78796799));
+
+        // without the leap second the local time doesn't exist
+        let utc = TimeZone::utc().unwrap();
+        let time = LocalDateTime::new(1972, 6, 30, 23, 59, 60).unwrap();
+        assert_eq!(utc.resolve_local(time).unwrap(), LocalResult::Gap(78796799, 78796800));
+        assert_eq!(utc.from_local(time).unwrap(), 78796800);
+        assert_eq!(utc.from_local_with(time, Disambiguation::ShiftForward).unwrap(), 78796800);
+        assert!(matches!(utc.from_local_with(time, Disambiguation::Reject), Err(TzError::Nonexistent)));
+        assert_eq!(crate::timegm(time).unwrap(), 78796800);
+    }
+
+    #[test]
+    fn leap_tables() {
+        let list = LeapTable::parse_leap_seconds_list(LEAP_SECONDS_LIST).unwrap();
+        let leaps = list.leap_seconds().iter().map(|leap| (leap.at(), leap.correction())).collect::<Vec<_>>();
+        assert_eq!(leaps, [(78796800, 1), (94694401, 2)]);
+        assert_eq!(list.updated(), Some(1751846400));
+        assert_eq!(list.expires(), Some(1782604800));
+        let corrupted = LEAP_SECONDS_LIST.replace("\t12\t", "\t13\t");
+        assert_eq!(LeapTable::parse_leap_seconds_list(&corrupted).unwrap_err().line(), 9);
+
+        let tzdb = LeapTable::parse_leapseconds(LEAPSECONDS).unwrap();
+        assert_eq!(tzdb, list);
+        let rolling = LEAPSECONDS.replace("+\tS", "+\tR");
+        assert_eq!(LeapTable::parse_leapseconds(&rolling).unwrap_err().line(), 2);
+
+        let zone = TimeZone::load("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        let right = zone.with_leap_table(&list).unwrap();
+        // plus the expiration
+        assert_eq!(right.leap_seconds().count(), 3);
+        assert_eq!(right.leap_table(), LeapTable { updated: None, ..list.clone() });
+        assert_eq!(right.to_local(94694401).unwrap().to_string(), "1973-01-01T00:59:60+01:00");
+        // 2021-07-01 00:00:00 UTC
+        assert_eq!(right.from_posix(1625097600).unwrap(), 1625097602);
+        assert_eq!(right.to_local(1625097602).unwrap().to_string(), "2021-07-01T02:00:00+02:00");
+        // the DST transition happens at the same POSIX time
+        let start = right.transitions(1616893100..).next().unwrap();
+        assert_eq!(right.to_posix(start.at()).unwrap(), 1616893200);
+
+        // a leap second right before the transition, its UTC time must be found by a search
+        let table = LeapTable::parse_leapseconds("Leap\t1972\tJun\t30\t23:59:60\t+\tS\nLeap\t2021\tMar\t28\t00:59:60\t+\tS\n").unwrap();
+        let near = zone.with_leap_table(&table).unwrap();
+        let start = near.transitions(1616893100..).next().unwrap();
+        assert_eq!(start.at(), 1616893202);
+        assert_eq!(near.to_posix(start.at()).unwrap(), 1616893200);
+
+        let plain = right.with_leap_table(&LeapTable::parse_leapseconds("").unwrap()).unwrap();
+        assert_eq!(plain.leap_seconds().count(), 0);
+        assert_eq!(plain.to_local(1625097600).unwrap().to_string(), "2021-07-01T02:00:00+02:00");
+    }
+}
@@ -12,8 +12,9 @@
 //! Use at your own risk or, better, help improve it!
 //!
 //! This is a fork of a C `localtime_r` implementation with minimal changes required to make
-//! calling it in parallel to setting env **from Rust** sound. It does so by calling into Rust code
-//! to get the environment variable instead of using raw system `getenv`.
+//! calling it in parallel to setting env **from Rust** sound. It does so by reading the environment
+//! variable and keeping the local time zone in Rust code instead of using raw system `getenv` and
+//! global variables.
 //!
 //! Obviously, this does **not** interact with the system implementation of `localtime_r`.
 //! E.g. if you call [`localtime`] in this crate it will not affect static variables in the system
@@ -24,15 +25,64 @@
 //! This crate is meant to be a cheaper-to-implement alternative to rewriting whole `localtime_r` in
 //! Rust which people are unwilling to do due to large code size. It only required changing a few
 //! lines and writing glue Rust code.
+//!
+//! If you need to work with multiple time zones or don't want to depend on `TZ` at all, use
+//! [`TimeZone`] instead of the global functions.
+//! To control the local time zone without modifying the process environment install an
+//! [`EnvSource`] or set the zone directly using [`set_local_zone`] or, for the current thread
+//! only, [`with_local_zone`].
 
-use std::io;
+use std::convert::TryFrom;
 use libc::time_t;
 use libc::c_char;
 
-extern "C" {
-    fn rl_localtime_r(sec: *const time_t, out: *mut libc::tm) -> *mut libc::tm;
-    fn rl_timegm(tm: *mut libc::tm) -> time_t;
-    fn rl_mktime(tm: *mut libc::tm) -> time_t;
+mod ffi;
+mod cache;
+mod datetime;
+mod env;
+mod error;
+mod leap;
+mod local;
+mod posix;
+mod reload;
+mod resolve;
+mod scale;
+mod sha1;
+mod smear;
+mod transition;
+mod tzif;
+mod zone;
+mod zoneinfo;
+
+pub use cache::ZoneCache;
+pub use datetime::{LocalDateTime, DateTimeError, Weekday, Abbreviation};
+pub use env::{EnvSource, ProcessEnv, StaticEnv, set_env_source, reset_env_source};
+pub use error::TzError;
+pub use leap::{LeapSecond, LeapSeconds, LeapTable, LeapTableError};
+pub use local::{LocalZoneSource, ZoneOrigin, LocalZoneError, set_local_zone, reset_local_zone, with_local_zone, local_zone_source, local_zone_origin, set_strict_local_zone};
+pub use posix::{PosixTz, PosixDst, PosixRule, RuleDate, PosixTzError};
+pub use reload::{ReloadPolicy, Reloaded, set_reload_policy, reload_policy, set_reload_hook, clear_reload_hook};
+pub use resolve::{LocalResult, Disambiguation};
+pub use scale::{TimeScale, GpsWeekTime};
+pub use smear::{LeapSmear, SmearShape};
+pub use transition::{Transition, Transitions};
+pub use tzif::{TzifError, TzifSection};
+pub use zone::TimeZone;
+pub use zoneinfo::{set_zoneinfo_path, reset_zoneinfo_path, zoneinfo_path};
+
+/// `tm_year` is the number of years since this year.
+pub(crate) const TM_YEAR_BASE: i32 = 1900;
+
+/// Size of the buffer for abbreviations passed to C code.
+pub(crate) const ABBR_BUF_SIZE: usize = datetime::ABBR_MAX_LEN + 1;
+
+/// Converts `tm` and abbreviation returned from C code to the typed representation.
+///
+/// The C code only produces valid values except when the year doesn't fit.
+pub(crate) fn tm_to_local(tm: libc::tm, abbr: &[c_char]) -> Result<LocalDateTime, TzError> {
+    LocalDateTime::try_from(tm)
+        .map(|time| time.with_abbreviation(Abbreviation::from_c(abbr)))
+        .map_err(|_| TzError::Overflow)
 }
 
 /// Converts Unix time to calendar time based on current locale.
@@ -40,34 +90,49 @@ extern "C" {
 /// This is a **sound** version of `localtime_r` from libc with proper locking.
 /// Calling this and concurently setting env **from Rust** using `std::env::set_var` is completely
 /// fine. Calling this in parallel is also fine.
-pub fn localtime(sec: time_t) -> io::Result<libc::tm> {
-    unsafe {
-        let mut out = std::mem::zeroed();
-        if rl_localtime_r(&sec, &mut out).is_null() {
-            return Err(io::Error::last_os_error());
-        }
-        Ok(out)
-    }
+///
+/// If `TZ` is invalid UTC is used unless [strict mode](set_strict_local_zone) is on. If the zone
+/// was set by [`set_local_zone`] or [`with_local_zone`] `TZ` is ignored.
+pub fn localtime(sec: time_t) -> Result<LocalDateTime, TzError> {
+    reload::check_local();
+    local::with_zone(|zone| zone.to_local(sec))?
 }
 
 /// Converts calendar time to Unix time using UTC timezone.
 ///
+/// POSIX UTC has no leap seconds so second 60 is normalized to the next minute.
+///
 /// Note that this method is soundly available even on platforms that normally don't have it.
-pub fn timegm(mut tm: libc::tm) -> time_t {
+pub fn timegm(time: LocalDateTime) -> Result<time_t, TzError> {
+    let mut tm = libc::tm::from(time);
+    let mut out = 0;
     // C functions happily modify the inputs... Garbage everywhere...
-    unsafe {
-        rl_timegm(&mut tm)
+    match unsafe { ffi::rl_timegm(&mut tm, &mut out) } {
+        0 => Ok(out),
+        _ => Err(TzError::Overflow),
     }
 }
 
 /// Converts calendar time to Unix time using local timezone.
 ///
 /// Note that this method is soundly available even on platforms that normally don't have it.
-pub fn mktime(mut tm: libc::tm) -> time_t {
-    // C functions happily modify the inputs... Garbage everywhere...
-    unsafe {
-        rl_mktime(&mut tm)
-    }
+pub fn mktime(time: LocalDateTime) -> Result<time_t, TzError> {
+    reload::check_local();
+    local::with_zone(|zone| zone.from_local(time))?
+}
+
+/// Finds all instants that have the given local time in the local timezone.
+///
+/// Unlike [`mktime`] this ignores the offset and DST flag of `time` and reports repeated or
+/// skipped local time explicitly.
+pub fn resolve_local(time: LocalDateTime) -> Result<LocalResult<time_t>, TzError> {
+    resolve::Resolver::new(&time, localtime).resolve()
+}
+
+/// Converts calendar time to Unix time using local timezone and the given policy for repeated or
+/// skipped local time.
+pub fn mktime_with(time: LocalDateTime, policy: Disambiguation) -> Result<time_t, TzError> {
+    resolve::Resolver::new(&time, localtime).resolve_with(policy)
 }
 
 /// Efficient C-compatible Option<Cow<OsStr>>
@@ -148,15 +213,11 @@ impl From<Option<std::ffi::OsString>> for COsString {
     }
 }
 
-/// Provides getenv Rust function to C code.
+/// Provides lookup of zone files in the [search path](zoneinfo_path) to C code.
 ///
-/// Rust implements proper locking, so this can be called safely from any thread.
 /// The returned value should be deallocated with [`rust_os_string_dealloc`].
-///
-/// **Important:** despite the value being owned the data behind `ptr` MUST NOT change in C code!
-/// `ptr` may point to static memory.
 #[no_mangle]
-extern "C" fn rust_getenv(name: *const c_char, name_len: usize) -> COsString {
+extern "C" fn rust_zoneinfo_file(name: *const c_char, name_len: usize) -> COsString {
     use std::os::unix::ffi::OsStrExt;
     use std::ffi::OsStr;
 
@@ -164,10 +225,10 @@ extern "C" fn rust_getenv(name: *const c_char, name_len: usize) -> COsString {
         let name = std::slice::from_raw_parts(name as *const u8, name_len);
         OsStr::from_bytes(name)
     };
-    std::env::var_os(name).into()
+    zoneinfo::find_zone_file(name).map(Into::into).into()
 }
 
-/// Deallocates C-compatible OS string returned from `rust_getenv`.
+/// Deallocates C-compatible OS string returned from `rust_zoneinfo_file`.
 #[no_mangle]
 extern "C" fn rust_os_string_dealloc(string: COsString) {
     unsafe {
@@ -175,23 +236,58 @@ extern "C" fn rust_os_string_dealloc(string: COsString) {
     }
 }
 
+/// Serializes the tests changing process-wide state, like `TZ` or the zoneinfo path, because
+/// tests run in parallel.
+#[cfg(test)]
+pub(crate) fn lock_globals() -> std::sync::MutexGuard<'static, ()> {
+    static GLOBALS: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    GLOBALS.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
 #[cfg(test)]
 mod tests {
-    // this is only one test to avoid problems with test parallelism
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use crate::{LocalZoneSource, StaticEnv, TimeZone, TzError, Weekday, ZoneOrigin};
+
+    // 2021-07-01 00:00:00 UTC
+    const SUMMER: libc::time_t = 1625097600;
+
+    fn abbreviation(time: libc::time_t) -> Option<String> {
+        super::localtime(time).unwrap().abbreviation().map(str::to_owned)
+    }
+
     #[test]
-    fn basic_test() {
+    fn conversions() {
+        super::with_local_zone(TimeZone::utc().unwrap(), || {
+            let time = super::localtime(0).unwrap();
+            assert_eq!(time.second(), 0);
+            assert_eq!(time.minute(), 0);
+            assert_eq!(time.hour(), 0);
+            assert_eq!(time.day(), 1);
+            assert_eq!(time.month(), 1);
+            assert_eq!(time.year(), 1970);
+            assert_eq!(time.day_of_year(), 1);
+            assert_eq!(time.weekday(), Weekday::Thursday);
+            assert_eq!(time.offset(), 0);
+            assert_ne!(time.is_dst(), Some(true));
+            assert_eq!(time.abbreviation(), Some("GMT"));
+            assert_eq!(super::timegm(time).unwrap(), 0);
+        });
+
+        super::with_local_zone(TimeZone::load("CET-1CEST,M3.5.0,M10.5.0/3").unwrap(), || {
+            let summer = super::localtime(SUMMER).unwrap();
+            assert_eq!(summer.abbreviation(), Some("CEST"));
+            assert_eq!(super::mktime(summer).unwrap(), SUMMER);
+        });
+    }
+
+    #[test]
+    fn process_env() {
+        let _globals = super::lock_globals();
+        super::reset_env_source();
         std::env::set_var("TZ", "");
-        let time = super::localtime(0).unwrap();
-        assert_eq!(time.tm_sec, 0);
-        assert_eq!(time.tm_min, 0);
-        assert_eq!(time.tm_hour, 0);
-        assert_eq!(time.tm_mday, 1);
-        assert_eq!(time.tm_mon, 0);
-        assert_eq!(time.tm_year, 70);
-        assert_eq!(time.tm_yday, 0);
-        assert_eq!(time.tm_wday, 4);
-        assert_eq!(time.tm_gmtoff, 0);
-        assert!(time.tm_isdst < 1);
+        assert_eq!(abbreviation(0).as_deref(), Some("GMT"));
 
         let setter_thread = std::thread::spawn(|| {
             for _ in 0..1000000 {
@@ -202,7 +298,106 @@ mod tests {
             super::localtime(0).unwrap();
         }
         setter_thread.join().unwrap();
+    }
+
+    #[test]
+    fn env_source() {
+        let _globals = super::lock_globals();
+        std::env::set_var("TZ", "");
+        super::set_env_source(StaticEnv::new().with("TZ", "CET-1CEST,M3.5.0,M10.5.0/3"));
+        assert_eq!(abbreviation(SUMMER).as_deref(), Some("CEST"));
+        super::set_env_source(|name: &str| if name == "TZ" { Some("EST5EDT,M3.2.0,M11.1.0".into()) } else { None });
+        assert_eq!(abbreviation(SUMMER).as_deref(), Some("EDT"));
+        super::reset_env_source();
+        assert_eq!(abbreviation(SUMMER).as_deref(), Some("GMT"));
+    }
+
+    #[test]
+    fn override_zone() {
+        let _globals = super::lock_globals();
+        super::reset_env_source();
+        std::env::set_var("TZ", "");
+        assert_eq!(super::local_zone_source(), LocalZoneSource::Environment);
+        super::set_local_zone(TimeZone::load("CET-1CEST,M3.5.0,M10.5.0/3").unwrap());
+        assert_eq!(super::local_zone_source(), LocalZoneSource::Override);
+        let summer = super::localtime(SUMMER).unwrap();
+        assert_eq!(summer.abbreviation(), Some("CEST"));
+        assert_eq!(super::mktime(summer).unwrap(), SUMMER);
+        super::reset_local_zone();
+        assert_eq!(super::local_zone_source(), LocalZoneSource::Environment);
+        assert_eq!(abbreviation(SUMMER).as_deref(), Some("GMT"));
+        assert!(matches!(super::local_zone_origin().unwrap(), ZoneOrigin::Utc));
+    }
+
+    #[test]
+    fn origin_and_strict_mode() {
+        let _globals = super::lock_globals();
+        super::set_env_source(StaticEnv::new().with("TZ", "CET-1CEST,M3.5.0,M10.5.0/3"));
+        assert!(matches!(super::local_zone_origin().unwrap(), ZoneOrigin::Posix(tz) if tz == "CET-1CEST,M3.5.0,M10.5.0/3"));
+        let summer = super::localtime(SUMMER).unwrap();
+        super::set_env_source(StaticEnv::new().with("TZ", "/nonexistent/Europe/Bratislva"));
+        match super::local_zone_origin().unwrap() {
+            ZoneOrigin::Fallback(error) => {
+                assert_eq!(error.path(), Some(std::path::Path::new("/nonexistent/Europe/Bratislva")));
+                assert!(matches!(error.error(), TzError::ZoneLoad(_)));
+            },
+            other => panic!("unexpected origin: {:?}", other),
+        }
+        assert_eq!(abbreviation(SUMMER).as_deref(), Some("GMT"));
+        super::set_strict_local_zone(true);
+        assert!(matches!(super::localtime(SUMMER), Err(TzError::LocalZone(_))));
+        assert!(matches!(super::mktime(summer), Err(TzError::LocalZone(_))));
+        super::set_strict_local_zone(false);
+        super::reset_env_source();
+    }
+
+    #[test]
+    fn invalidated_during_load() {
+        static INVALIDATE: AtomicBool = AtomicBool::new(false);
 
-        assert_eq!(super::timegm(time), 0);
+        let _globals = super::lock_globals();
+        super::set_env_source(|name: &str| match name {
+            "TZ" => Some("Nonexistent/Zone".into()),
+            _ => {
+                if INVALIDATE.swap(false, Ordering::Relaxed) {
+                    crate::local::invalidate();
+                }
+                None
+            },
+        });
+        let error = |origin| match origin {
+            ZoneOrigin::Fallback(error) => error,
+            other => panic!("unexpected origin: {:?}", other),
+        };
+        INVALIDATE.store(true, Ordering::Relaxed);
+        // the zone loaded during the invalidation must not stay published
+        let interrupted = error(super::local_zone_origin().unwrap());
+        let reloaded = error(super::local_zone_origin().unwrap());
+        assert!(!Arc::ptr_eq(&interrupted, &reloaded));
+        assert!(Arc::ptr_eq(&reloaded, &error(super::local_zone_origin().unwrap())));
+        super::reset_env_source();
+    }
+
+    #[test]
+    fn tzdir_from_env_source() {
+        static TZDIR: std::sync::Mutex<Option<std::path::PathBuf>> = std::sync::Mutex::new(None);
+
+        let _globals = super::lock_globals();
+        let dir = std::env::temp_dir().join(format!("rl_localtime-tzdir-{}", std::process::id()));
+        for (name, offset) in &[("a", 3600), ("b", 7200)] {
+            std::fs::create_dir_all(dir.join(name)).unwrap();
+            std::fs::write(dir.join(name).join("Zone"), crate::tzif::fixed(*offset, "ABC")).unwrap();
+        }
+        *TZDIR.lock().unwrap() = Some(dir.join("a"));
+        super::set_env_source(|name: &str| match name {
+            "TZ" => Some("Zone".into()),
+            "TZDIR" => TZDIR.lock().unwrap().clone().map(Into::into),
+            _ => None,
+        });
+        assert_eq!(super::localtime(0).unwrap().offset(), 3600);
+        *TZDIR.lock().unwrap() = Some(dir.join("b"));
+        assert_eq!(super::localtime(0).unwrap().offset(), 7200);
+        std::fs::remove_dir_all(&dir).unwrap();
+        super::reset_env_source();
     }
 }
@@ -0,0 +1,379 @@
+//! The local time zone selected by `TZ` or set by the application, possibly only for the current
+//! thread.
+//!
+//! The zone is loaded without holding any lock into a fresh [`TimeZone`] which is then published
+//! as an immutable snapshot. Each thread keeps its own reference to the snapshot and only checks
+//! a global generation counter to find out whether it's still current, so conversions don't write
+//! to any shared memory and never block each other. Only loading a new zone takes a lock.
+
+use std::cell::RefCell;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, PoisonError};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use crate::{env, reload, zoneinfo, TimeZone, TzError};
+use crate::zone::ZoneKind;
+
+/// Where the local time zone comes from.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum LocalZoneSource {
+    /// The zone is loaded according to `TZ` from the [`EnvSource`](crate::EnvSource).
+    Environment,
+    /// The zone was set by [`set_local_zone`].
+    Override,
+    /// The zone was set for the current thread by [`with_local_zone`].
+    Scoped,
+}
+
+/// How the local time zone was obtained.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum ZoneOrigin {
+    /// The zone was loaded from this TZif file, either named by `TZ` or `/etc/localtime` if `TZ`
+    /// is not set.
+    File(PathBuf),
+    /// `TZ` was parsed as a POSIX TZ string.
+    Posix(OsString),
+    /// `TZ` is empty which means UTC.
+    Utc,
+    /// The zone was set by [`set_local_zone`] or [`with_local_zone`].
+    Override,
+    /// The zone could not be loaded so UTC is used instead.
+    Fallback(Arc<LocalZoneError>),
+}
+
+/// Failure to load the local time zone.
+#[derive(Debug)]
+pub struct LocalZoneError {
+    tz: Option<OsString>,
+    path: Option<PathBuf>,
+    error: TzError,
+}
+
+impl LocalZoneError {
+    /// Returns the value of `TZ`, `None` if it's not set.
+    pub fn tz(&self) -> Option<&OsStr> {
+        self.tz.as_deref()
+    }
+
+    /// Returns the path of the file that was tried, `None` if no zoneinfo directory is
+    /// configured.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Returns the error returned when loading the zone.
+    ///
+    /// If `TZ` is not a file the error is the one from reading the file, not from parsing `TZ`
+    /// as a POSIX TZ string.
+    pub fn error(&self) -> &TzError {
+        &self.error
+    }
+}
+
+impl fmt::Display for LocalZoneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.tz {
+            Some(tz) => write!(f, "time zone {:?} from TZ", tz)?,
+            None => write!(f, "default time zone")?,
+        }
+        match &self.path {
+            Some(path) => write!(f, " could not be loaded from {}", path.display()),
+            None => write!(f, " could not be loaded"),
+        }
+    }
+}
+
+impl std::error::Error for LocalZoneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// What a snapshot was created for.
+#[derive(Clone, Eq, PartialEq)]
+enum Key {
+    /// The values of `TZ` and `TZDIR` the zone was loaded for, `TZDIR` only if the zone is
+    /// searched for in the zoneinfo directories.
+    Env {
+        tz: Option<OsString>,
+        tzdir: Option<OsString>,
+    },
+    Override,
+}
+
+#[derive(Clone)]
+struct Snapshot {
+    key: Key,
+    zone: Arc<TimeZone>,
+    origin: ZoneOrigin,
+}
+
+/// The latest snapshot loaded according to `TZ`, `None` if the zone wasn't loaded yet or was
+/// invalidated.
+static CURRENT: RwLock<Option<Snapshot>> = RwLock::new(None);
+
+/// The zone set by [`set_local_zone`].
+static OVERRIDE: RwLock<Option<Arc<TimeZone>>> = RwLock::new(None);
+
+/// Avoids reading `TZ` if the zone is overridden.
+static OVERRIDDEN: AtomicBool = AtomicBool::new(false);
+
+/// Set by [`set_strict_local_zone`].
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// Incremented every time `CURRENT` or `OVERRIDE` changes.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// Snapshot used by this thread and the generation it was taken at.
+    static CACHED: RefCell<Option<(u64, Snapshot)>> = const { RefCell::new(None) };
+
+    /// The zone set by [`with_local_zone`].
+    static SCOPED: RefCell<Option<Arc<TimeZone>>> = const { RefCell::new(None) };
+}
+
+/// Loads the zone watching its file if the [reload policy](crate::set_reload_policy) says so.
+fn load(tz: Option<&OsStr>) -> Result<(TimeZone, ZoneOrigin), TzError> {
+    let watched = reload::watch_local(tz);
+    let loaded = load_zone(tz)?;
+    if let Some(watched) = watched {
+        reload::local_loaded(tz, watched);
+    }
+    Ok(loaded)
+}
+
+/// Loads the zone the same way `tzsetlcl` in the C code did.
+///
+/// Unset `TZ` means `/etc/localtime`. If the zone can't be loaded UTC is used.
+fn load_zone(tz: Option<&OsStr>) -> Result<(TimeZone, ZoneOrigin), TzError> {
+    let path = || zoneinfo::zone_file_path(tz);
+    match TimeZone::load_tz(tz) {
+        Ok((zone, ZoneKind::Utc)) => Ok((zone, ZoneOrigin::Utc)),
+        // the path is only missing for empty names which are UTC
+        Ok((zone, ZoneKind::File)) => Ok((zone, ZoneOrigin::File(path().unwrap_or_default()))),
+        // the default zone is never parsed
+        Ok((zone, ZoneKind::Posix)) => Ok((zone, ZoneOrigin::Posix(tz.unwrap_or_default().to_owned()))),
+        Err(error) => {
+            let error = LocalZoneError {
+                tz: tz.map(ToOwned::to_owned),
+                path: path(),
+                error,
+            };
+            Ok((TimeZone::utc()?, ZoneOrigin::Fallback(Arc::new(error))))
+        },
+    }
+}
+
+fn publish(snapshot: Option<Snapshot>) {
+    let mut current = CURRENT.write().unwrap_or_else(PoisonError::into_inner);
+    *current = snapshot;
+    // incremented under the lock so that `current` can't publish a snapshot loaded before this
+    GENERATION.fetch_add(1, Ordering::Release);
+}
+
+/// Returns the current snapshot for `key` loading the zone if needed.
+///
+/// `generation` is the value of `GENERATION` read before `key`. The loaded snapshot is published
+/// only if nothing was published or invalidated since then, otherwise it's returned without
+/// publishing so the next call loads the zone again.
+fn current(key: Key, generation: u64) -> Result<Snapshot, TzError> {
+    let (tz, tzdir) = match key {
+        Key::Env { tz, tzdir } => (tz, tzdir),
+        Key::Override => {
+            if let Some(zone) = &*OVERRIDE.read().unwrap_or_else(PoisonError::into_inner) {
+                return Ok(Snapshot { key: Key::Override, zone: Arc::clone(zone), origin: ZoneOrigin::Override });
+            }
+            // reset in the meantime
+            let tz = env::var_os("TZ");
+            let tzdir = zoneinfo::tzdir_for(tz.as_deref());
+            (tz, tzdir)
+        },
+    };
+
+    if let Some(current) = &*CURRENT.read().unwrap_or_else(PoisonError::into_inner) {
+        if matches!(&current.key, Key::Env { tz: current_tz, tzdir: current_tzdir } if *current_tz == tz && *current_tzdir == tzdir) {
+            return Ok(current.clone());
+        }
+    }
+
+    let (zone, origin) = load(tz.as_deref())?;
+    let snapshot = Snapshot { key: Key::Env { tz, tzdir }, zone: Arc::new(zone), origin };
+    let mut current = CURRENT.write().unwrap_or_else(PoisonError::into_inner);
+    if GENERATION.load(Ordering::Acquire) == generation {
+        *current = Some(snapshot.clone());
+        GENERATION.fetch_add(1, Ordering::Release);
+    }
+    Ok(snapshot)
+}
+
+/// Calls `f` with the local time zone and its origin loading it if `TZ` or `TZDIR` changed since
+/// the last call.
+///
+/// The variables are compared with the values in the snapshot by
+/// [`EnvSource::var_eq`](crate::EnvSource::var_eq) so they are copied only when the zone is
+/// loaded, unless the source can't lend them. `TZDIR` is compared only if `TZ` names a zone
+/// searched for in the zoneinfo directories.
+///
+/// The default source reads the process environment through `std::env` so that it's
+/// synchronized with `std::env::set_var`. Sadly, std can't lend a value under its lock and
+/// `getenv` would race with `set_var`, so this allocates once if `TZ` is set in the process
+/// environment. If it's unset, which is the common case, nothing is allocated.
+fn with_snapshot<R, F: FnOnce(&TimeZone, &ZoneOrigin) -> R>(f: F) -> Result<R, TzError> {
+    // cloned so that `f` can call `with_local_zone`
+    if let Some(zone) = SCOPED.with(|scoped| scoped.borrow().clone()) {
+        return Ok(f(&zone, &ZoneOrigin::Override));
+    }
+    CACHED.with(|cached| {
+        // read before the snapshot so that a concurrent change is noticed on the next call
+        let generation = GENERATION.load(Ordering::Acquire);
+        let overridden = OVERRIDDEN.load(Ordering::Acquire);
+        // taken out so that the `EnvSource` and `f` can use the local zone too
+        let mut entry = cached.borrow_mut().take();
+        let is_current = match &entry {
+            Some((cached_generation, snapshot)) if *cached_generation == generation => match &snapshot.key {
+                Key::Override => overridden,
+                Key::Env { tz, tzdir } => {
+                    !overridden
+                        && env::var_eq("TZ", tz.as_deref())
+                        && (!zoneinfo::is_searched(tz.as_deref()) || env::var_eq("TZDIR", tzdir.as_deref()))
+                },
+            },
+            _ => false,
+        };
+        if !is_current {
+            let key = if overridden {
+                Key::Override
+            } else {
+                let tz = env::var_os("TZ");
+                let tzdir = zoneinfo::tzdir_for(tz.as_deref());
+                Key::Env { tz, tzdir }
+            };
+            entry = Some((generation, current(key, generation)?));
+        }
+        let (_, snapshot) = entry.as_ref().expect("the snapshot was stored above");
+        let result = f(&snapshot.zone, &snapshot.origin);
+        *cached.borrow_mut() = entry;
+        Ok(result)
+    })
+}
+
+/// Calls `f` with the local time zone failing in strict mode if it couldn't be loaded.
+pub(crate) fn with_zone<R, F: FnOnce(&TimeZone) -> R>(f: F) -> Result<R, TzError> {
+    with_snapshot(|zone, origin| match origin {
+        ZoneOrigin::Fallback(error) if STRICT.load(Ordering::Relaxed) => Err(TzError::LocalZone(Arc::clone(error))),
+        _ => Ok(f(zone)),
+    })?
+}
+
+/// Makes the next conversion load the local zone again even if `TZ` didn't change.
+pub(crate) fn invalidate() {
+    publish(None);
+}
+
+/// Sets the local time zone used by [`localtime`](crate::localtime) and other functions of this
+/// crate for the whole process.
+///
+/// `TZ` is ignored until [`reset_local_zone`] is called. Accepts both [`TimeZone`] and
+/// `Arc<TimeZone>` so zones from a [`ZoneCache`](crate::ZoneCache) can be used without loading
+/// them again.
+pub fn set_local_zone<Z: Into<Arc<TimeZone>>>(zone: Z) {
+    *OVERRIDE.write().unwrap_or_else(PoisonError::into_inner) = Some(zone.into());
+    OVERRIDDEN.store(true, Ordering::Release);
+    GENERATION.fetch_add(1, Ordering::Release);
+}
+
+/// Undoes [`set_local_zone`] so the local time zone is selected by `TZ` again.
+pub fn reset_local_zone() {
+    OVERRIDDEN.store(false, Ordering::Release);
+    *OVERRIDE.write().unwrap_or_else(PoisonError::into_inner) = None;
+    GENERATION.fetch_add(1, Ordering::Release);
+}
+
+/// Calls `f` with the local time zone of the current thread set to `zone`.
+///
+/// Conversions made by `f` on this thread use `zone` regardless of `TZ` and [`set_local_zone`],
+/// other threads are not affected. The previous zone is restored when `f` returns or panics, so
+/// the calls can be nested. This is useful for tests running in parallel or for handling requests
+/// of users in different zones.
+pub fn with_local_zone<Z: Into<Arc<TimeZone>>, R, F: FnOnce() -> R>(zone: Z, f: F) -> R {
+    struct Restore(Option<Arc<TimeZone>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            SCOPED.with(|scoped| *scoped.borrow_mut() = previous);
+        }
+    }
+
+    let previous = SCOPED.with(|scoped| scoped.borrow_mut().replace(zone.into()));
+    let _restore = Restore(previous);
+    f()
+}
+
+/// Returns how the local time zone of the current thread was obtained loading it if needed.
+///
+/// This can be used to report a `TZ` that couldn't be loaded, e.g. due to a typo.
+pub fn local_zone_origin() -> Result<ZoneOrigin, TzError> {
+    with_snapshot(|_, origin| origin.clone())
+}
+
+/// Makes [`localtime`](crate::localtime) and other functions using the local time zone return
+/// [`TzError::LocalZone`] instead of using UTC if the zone can't be loaded.
+///
+/// This is off by default, like in the C library.
+pub fn set_strict_local_zone(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
+}
+
+/// Returns where the local time zone of the current thread comes from.
+pub fn local_zone_source() -> LocalZoneSource {
+    if SCOPED.with(|scoped| scoped.borrow().is_some()) {
+        LocalZoneSource::Scoped
+    } else if OVERRIDDEN.load(Ordering::Acquire) {
+        LocalZoneSource::Override
+    } else {
+        LocalZoneSource::Environment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::{LocalZoneSource, TimeZone};
+
+    #[test]
+    fn scoped_zone() {
+        // 2021-07-01 00:00:00 UTC
+        let abbreviation = || crate::localtime(1625097600).unwrap().abbreviation().map(str::to_owned);
+        let threads = ["CET-1CEST,M3.5.0,M10.5.0/3", "EST5EDT,M3.2.0,M11.1.0"]
+            .iter()
+            .map(|&name| std::thread::spawn(move || {
+                let zone = TimeZone::load(name).unwrap();
+                let expected = zone.to_local(1625097600).unwrap().abbreviation().map(str::to_owned);
+                super::with_local_zone(zone, || {
+                    for _ in 0..1000 {
+                        assert_eq!(abbreviation(), expected);
+                    }
+                })
+            }))
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let cet = Arc::new(TimeZone::load("CET-1CEST,M3.5.0,M10.5.0/3").unwrap());
+        let utc = TimeZone::utc().unwrap();
+        let result = std::panic::catch_unwind(|| super::with_local_zone(Arc::clone(&cet), || {
+            assert_eq!(super::local_zone_source(), LocalZoneSource::Scoped);
+            super::with_local_zone(utc, || assert_eq!(abbreviation().as_deref(), Some("GMT")));
+            assert_eq!(abbreviation().as_deref(), Some("CEST"));
+            panic!("restored anyway");
+        }));
+        // any other panic is a failed assertion
+        assert_eq!(*result.unwrap_err().downcast::<&str>().unwrap(), "restored anyway");
+        assert_ne!(super::local_zone_source(), LocalZoneSource::Scoped);
+    }
+}
@@ -0,0 +1,462 @@
+//! Parsing and formatting of POSIX TZ strings.
+//!
+//! The grammar is the one accepted by `tzparse` in the C code, including its extensions: quoted
+//! names (`<+0330>`), hours up to 167 and signed transition times.
+
+use std::fmt;
+use std::str::FromStr;
+use crate::Weekday;
+
+const SECS_PER_HOUR: i32 = 3600;
+
+/// Transition time used when a rule doesn't specify one.
+const DEFAULT_RULE_TIME: i32 = 2 * SECS_PER_HOUR;
+
+/// Maximum total length of both names including their terminating null bytes.
+///
+/// Must match `2 * (MY_TZNAME_MAX + 1)` in the C code.
+const MAX_NAMES_LEN: usize = 2 * (255 + 1);
+
+/// Parsed POSIX TZ string such as `CET-1CEST,M3.5.0,M10.5.0/3`.
+///
+/// Offsets returned from this type follow the convention of the rest of the crate: they are the
+/// number of seconds *east* of UT. This is the opposite of the sign written in the string.
+///
+/// Formatting produces the canonical form: names are quoted only when needed, default values are
+/// omitted and rules are separated by commas.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct PosixTz {
+    std_name: String,
+    std_offset: i32,
+    dst: Option<PosixDst>,
+}
+
+impl PosixTz {
+    /// Returns the abbreviation of standard time.
+    pub fn std_name(&self) -> &str {
+        &self.std_name
+    }
+
+    /// Returns the UT offset of standard time in seconds.
+    pub fn std_offset(&self) -> i32 {
+        self.std_offset
+    }
+
+    /// Returns the description of daylight saving time if the zone has it.
+    pub fn dst(&self) -> Option<&PosixDst> {
+        self.dst.as_ref()
+    }
+}
+
+/// Daylight saving time part of [`PosixTz`].
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct PosixDst {
+    name: String,
+    offset: i32,
+    rules: Option<(PosixRule, PosixRule)>,
+}
+
+impl PosixDst {
+    /// Returns the abbreviation of daylight saving time.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the UT offset of daylight saving time in seconds.
+    ///
+    /// If the string doesn't specify it this is one hour more than the standard offset.
+    pub fn offset(&self) -> i32 {
+        self.offset
+    }
+
+    /// Returns the rules for the start and the end of daylight saving time.
+    ///
+    /// If the string doesn't contain them the C code uses the rules from the `posixrules` file
+    /// or, if that can't be loaded, `M4.1.0,M10.5.0`.
+    pub fn rules(&self) -> Option<(PosixRule, PosixRule)> {
+        self.rules
+    }
+}
+
+/// Rule describing when daylight saving time starts or ends.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct PosixRule {
+    date: RuleDate,
+    time: i32,
+}
+
+impl PosixRule {
+    /// Returns the day on which the transition happens.
+    pub fn date(&self) -> RuleDate {
+        self.date
+    }
+
+    /// Returns the local time of the transition in seconds since the midnight of the day.
+    ///
+    /// It may be negative or exceed one day. The default is 02:00:00.
+    pub fn time(&self) -> i32 {
+        self.time
+    }
+}
+
+/// Day of the year specified by [`PosixRule`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum RuleDate {
+    /// `Jn` - Julian day from 1 to 365 not counting February 29.
+    Julian(u16),
+    /// `n` - zero-based day of the year from 0 to 365 counting February 29.
+    DayOfYear(u16),
+    /// `Mm.w.d` - the `week`-th `weekday` of `month`, week 5 meaning the last one.
+    MonthWeekday {
+        /// Month from 1 to 12.
+        month: u8,
+        /// Week from 1 to 5.
+        week: u8,
+        /// Day of the week.
+        weekday: Weekday,
+    },
+}
+
+/// Error returned when parsing an invalid POSIX TZ string.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct PosixTzError {
+    position: usize,
+    expected: &'static str,
+}
+
+impl PosixTzError {
+    /// Returns the byte position in the string at which parsing failed.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl fmt::Display for PosixTzError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid TZ string: expected {} at byte {}", self.expected, self.position)
+    }
+}
+
+impl std::error::Error for PosixTzError {}
+
+/// Recursive descent parser mirroring `tzparse` and its helpers.
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.input.as_bytes().get(self.pos).copied()
+    }
+
+    fn error(&self, position: usize, expected: &'static str) -> PosixTzError {
+        PosixTzError { position, expected }
+    }
+
+    fn expect(&mut self, byte: u8, expected: &'static str) -> Result<(), PosixTzError> {
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(self.pos, expected))
+        }
+    }
+
+    /// Parses a name like [`name`](Self::name) failing if it doesn't fit into the room left after
+    /// `used` bytes of names.
+    fn limited_name(&mut self, used: usize) -> Result<&'a str, PosixTzError> {
+        let name = self.name()?;
+        if used + name.len() + 1 > MAX_NAMES_LEN {
+            let start = name.as_ptr() as usize - self.input.as_ptr() as usize;
+            return Err(self.error(start + (MAX_NAMES_LEN - used - 1), "shorter name"));
+        }
+        Ok(name)
+    }
+
+    /// Parses a quoted (`getqzname`) or unquoted (`getzname`) name.
+    fn name(&mut self) -> Result<&'a str, PosixTzError> {
+        let start = self.pos;
+        let name = if self.peek() == Some(b'<') {
+            self.pos += 1;
+            let len = self.input[self.pos..].find('>').ok_or_else(|| self.error(self.input.len(), "'>'"))?;
+            let name = &self.input[self.pos..(self.pos + len)];
+            self.pos += len + 1;
+            name
+        } else {
+            let len = self.input[start..]
+                .find(|c: char| c.is_ascii_digit() || c == ',' || c == '-' || c == '+' || c == '\0')
+                .unwrap_or(self.input.len() - start);
+            self.pos += len;
+            &self.input[start..self.pos]
+        };
+        if name.is_empty() {
+            return Err(self.error(start, "name"));
+        }
+        Ok(name)
+    }
+
+    /// Parses a decimal number in `min..=max` (`getnum`).
+    fn num(&mut self, min: u32, max: u32) -> Result<u32, PosixTzError> {
+        let start = self.pos;
+        let mut num = 0u32;
+        while let Some(digit @ b'0'..=b'9') = self.peek() {
+            num = num * 10 + u32::from(digit - b'0');
+            if num > max {
+                return Err(self.error(start, "number in range"));
+            }
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return Err(self.error(start, "number"));
+        }
+        if num < min {
+            return Err(self.error(start, "number in range"));
+        }
+        Ok(num)
+    }
+
+    /// Parses `hh[:mm[:ss]]` (`getsecs`).
+    fn secs(&mut self) -> Result<i32, PosixTzError> {
+        // up to one week to allow rules like M10.4.6/26
+        let mut secs = self.num(0, 24 * 7 - 1)? as i32 * SECS_PER_HOUR;
+        if self.peek() == Some(b':') {
+            self.pos += 1;
+            secs += self.num(0, 59)? as i32 * 60;
+            if self.peek() == Some(b':') {
+                self.pos += 1;
+                // 60 allows for leap seconds
+                secs += self.num(0, 60)? as i32;
+            }
+        }
+        Ok(secs)
+    }
+
+    /// Parses `[+-]hh[:mm[:ss]]` (`getoffset`) returning the value as written.
+    fn offset(&mut self) -> Result<i32, PosixTzError> {
+        match self.peek() {
+            Some(b'-') => {
+                self.pos += 1;
+                Ok(-self.secs()?)
+            },
+            Some(b'+') => {
+                self.pos += 1;
+                self.secs()
+            },
+            _ => self.secs(),
+        }
+    }
+
+    /// Parses `date[/time]` (`getrule`).
+    fn rule(&mut self) -> Result<PosixRule, PosixTzError> {
+        let date = match self.peek() {
+            Some(b'J') => {
+                self.pos += 1;
+                RuleDate::Julian(self.num(1, 365)? as u16)
+            },
+            Some(b'M') => {
+                self.pos += 1;
+                let month = self.num(1, 12)? as u8;
+                self.expect(b'.', "'.'")?;
+                let week = self.num(1, 5)? as u8;
+                self.expect(b'.', "'.'")?;
+                let weekday = Weekday::from_days_since_sunday(self.num(0, 6)? as u8)
+                    .expect("the range was checked");
+                RuleDate::MonthWeekday { month, week, weekday }
+            },
+            Some(b'0'..=b'9') => RuleDate::DayOfYear(self.num(0, 365)? as u16),
+            _ => return Err(self.error(self.pos, "rule")),
+        };
+        let time = if self.peek() == Some(b'/') {
+            self.pos += 1;
+            self.offset()?
+        } else {
+            DEFAULT_RULE_TIME
+        };
+        Ok(PosixRule { date, time })
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.pos == self.input.len()
+    }
+
+    fn parse(mut self) -> Result<PosixTz, PosixTzError> {
+        let std_name = self.limited_name(0)?.to_owned();
+        let std_offset = -self.offset()?;
+        if self.is_at_end() {
+            return Ok(PosixTz { std_name, std_offset, dst: None });
+        }
+
+        let name = self.limited_name(std_name.len() + 1)?.to_owned();
+        let offset = match self.peek() {
+            None | Some(b',') | Some(b';') => std_offset + SECS_PER_HOUR,
+            Some(_) => -self.offset()?,
+        };
+        let rules = match self.peek() {
+            None => None,
+            Some(b',') | Some(b';') => {
+                self.pos += 1;
+                let start = self.rule()?;
+                self.expect(b',', "','")?;
+                let end = self.rule()?;
+                if !self.is_at_end() {
+                    return Err(self.error(self.pos, "end of string"));
+                }
+                Some((start, end))
+            },
+            Some(_) => return Err(self.error(self.pos, "',' or end of string")),
+        };
+        Ok(PosixTz {
+            std_name,
+            std_offset,
+            dst: Some(PosixDst { name, offset, rules }),
+        })
+    }
+}
+
+impl FromStr for PosixTz {
+    type Err = PosixTzError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Parser { input: s, pos: 0 }.parse()
+    }
+}
+
+/// Writes the name quoting it if it would not be parsed back as the same unquoted name.
+fn write_name(f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+    // Names containing '>' could only have been parsed unquoted.
+    if name.bytes().all(|b| b.is_ascii_alphabetic()) || name.contains('>') {
+        write!(f, "{}", name)
+    } else {
+        write!(f, "<{}>", name)
+    }
+}
+
+/// Writes `[-]h[:mm[:ss]]` omitting zero minutes and seconds.
+fn write_time(f: &mut fmt::Formatter<'_>, time: i32) -> fmt::Result {
+    if time < 0 {
+        write!(f, "-")?;
+    }
+    let time = time.abs();
+    write!(f, "{}", time / SECS_PER_HOUR)?;
+    if time % SECS_PER_HOUR != 0 {
+        write!(f, ":{:02}", time / 60 % 60)?;
+        if time % 60 != 0 {
+            write!(f, ":{:02}", time % 60)?;
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for PosixRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.date {
+            RuleDate::Julian(day) => write!(f, "J{}", day)?,
+            RuleDate::DayOfYear(day) => write!(f, "{}", day)?,
+            RuleDate::MonthWeekday { month, week, weekday } => write!(f, "M{}.{}.{}", month, week, weekday.days_since_sunday())?,
+        }
+        if self.time != DEFAULT_RULE_TIME {
+            write!(f, "/")?;
+            write_time(f, self.time)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for PosixTz {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_name(f, &self.std_name)?;
+        write_time(f, -self.std_offset)?;
+        if let Some(dst) = &self.dst {
+            write_name(f, &dst.name)?;
+            if dst.offset != self.std_offset + SECS_PER_HOUR {
+                write_time(f, -dst.offset)?;
+            }
+            if let Some((start, end)) = &dst.rules {
+                write!(f, ",{},{}", start, end)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+    use super::{PosixTz, RuleDate};
+    use crate::{TimeZone, Weekday};
+    use crate::zone::ZoneKind;
+
+    #[test]
+    fn parse_and_format() {
+        let tz = "CET-1CEST,M3.5.0,M10.5.0/3".parse::<PosixTz>().unwrap();
+        assert_eq!(tz.std_name(), "CET");
+        assert_eq!(tz.std_offset(), 3600);
+        let dst = tz.dst().unwrap();
+        assert_eq!(dst.name(), "CEST");
+        assert_eq!(dst.offset(), 7200);
+        let (start, end) = dst.rules().unwrap();
+        assert_eq!(start.date(), RuleDate::MonthWeekday { month: 3, week: 5, weekday: Weekday::Sunday });
+        assert_eq!(start.time(), 7200);
+        assert_eq!(end.time(), 10800);
+        assert_eq!(tz.to_string(), "CET-1CEST,M3.5.0,M10.5.0/3");
+
+        let tz = "<+0330>-3:30".parse::<PosixTz>().unwrap();
+        assert_eq!(tz.std_name(), "+0330");
+        assert_eq!(tz.std_offset(), 12600);
+        assert!(tz.dst().is_none());
+        assert_eq!(tz.to_string(), "<+0330>-3:30");
+
+        let canonical = |s: &str| s.parse::<PosixTz>().unwrap().to_string();
+        assert_eq!(canonical("<EST>+05:00:00<EDT>4;J60/2:00,300/-1:30:15"), "EST5EDT,J60,300/-1:30:15");
+        assert_eq!(canonical("IST-2IDT,M3.4.4/26,M10.5.0"), "IST-2IDT,M3.4.4/26,M10.5.0");
+        assert_eq!(canonical("EST5EDT"), "EST5EDT");
+        assert_eq!(canonical("<-03>3<-02>,M3.5.0/-2,M10.5.0/-1"), "<-03>3<-02>,M3.5.0/-2,M10.5.0/-1");
+    }
+
+    #[test]
+    fn errors() {
+        let position = |s: &str| s.parse::<PosixTz>().unwrap_err().position();
+        assert_eq!(position(""), 0);
+        assert_eq!(position("CET"), 3);
+        assert_eq!(position("<CET-1"), 6);
+        assert_eq!(position("CET-168"), 4);
+        assert_eq!(position("CET-1:60"), 6);
+        assert_eq!(position("CET-1CEST,M13.5.0,M10.5.0"), 11);
+        assert_eq!(position("CET-1CEST,M3.5.0"), 16);
+        assert_eq!(position("CET-1CEST,M3.5.0,M10.5.0x"), 24);
+        assert_eq!(position("CET-1CEST-2x"), 11);
+        assert_eq!(position("CET-1CEST,J0,J365"), 11);
+
+        // the names can have 510 bytes in total, or 511 if there's only one
+        let long = "X".repeat(512);
+        assert_eq!(position(&format!("{}-1", long)), 511);
+        assert_eq!(position(&format!("<{}>-1", long)), 512);
+        assert_eq!(position(&format!("{}-1{}", &long[..500], &long[..11])), 512);
+    }
+
+    #[test]
+    fn accepted_strings_load() {
+        let long = "X".repeat(511);
+        let strings = [
+            "CET-1CEST,M3.5.0,M10.5.0/3".to_owned(),
+            "<+0330>-3:30".to_owned(),
+            "<EST>+05:00:00<EDT>4;J60/2:00,300/-1:30:15".to_owned(),
+            "IST-2IDT,M3.4.4/26,M10.5.0".to_owned(),
+            "<-03>3<-02>,M3.5.0/-2,M10.5.0/-1".to_owned(),
+            format!("{}-1", long),
+            format!("<{}>-1", long),
+            format!("{}-1{},M3.5.0,M10.5.0/3", &long[..500], &long[..10]),
+        ];
+        for string in &strings {
+            let tz = string.parse::<PosixTz>().unwrap();
+            for string in &[string.clone(), tz.to_string()] {
+                let (_, kind) = TimeZone::load_tz(Some(OsStr::new(string))).unwrap();
+                assert_eq!(kind, ZoneKind::Posix, "{}", string);
+            }
+        }
+        let too_long = format!("{}-1{},M3.5.0,M10.5.0/3", &long[..500], &long[..11]);
+        assert!(too_long.parse::<PosixTz>().is_err());
+        assert!(TimeZone::load_tz(Some(OsStr::new(&too_long))).is_err());
+    }
+}
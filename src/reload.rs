@@ -0,0 +1,292 @@
+//! Reloading of time zones when their files change on disk.
+
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock, PoisonError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use crate::{env, local, zoneinfo, LocalZoneSource};
+
+/// When to check whether the file a zone was loaded from changed.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+pub enum ReloadPolicy {
+    /// Never reload the zone once it's loaded (the default).
+    #[default]
+    Never,
+    /// Check the file at most once per the given interval during conversions and reload the zone
+    /// if the file was modified or replaced.
+    ///
+    /// The check is a `stat` of the file comparing its device, inode, size and modification time.
+    CheckEvery(Duration),
+}
+
+/// Zone that was reloaded, passed to the hook set by [`set_reload_hook`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum Reloaded<'a> {
+    /// The local time zone will be loaded again on the next conversion.
+    LocalZone,
+    /// The zone with this name was loaded again in a [`ZoneCache`](crate::ZoneCache).
+    CachedZone(&'a str),
+}
+
+type Hook = Arc<dyn Fn(Reloaded<'_>) + Send + Sync>;
+
+static HOOK: RwLock<Option<Hook>> = RwLock::new(None);
+
+/// Sets the function called after a zone was reloaded because its file changed.
+///
+/// The hook is called from the thread that performed the conversion which detected the change.
+pub fn set_reload_hook<F: Fn(Reloaded<'_>) + Send + Sync + 'static>(hook: F) {
+    *HOOK.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(hook));
+}
+
+/// Removes the hook set by [`set_reload_hook`].
+pub fn clear_reload_hook() {
+    *HOOK.write().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+pub(crate) fn notify(zone: Reloaded<'_>) {
+    // cloned so that the hook can replace itself
+    let hook = HOOK.read().unwrap_or_else(PoisonError::into_inner).clone();
+    if let Some(hook) = hook {
+        hook(zone);
+    }
+}
+
+/// Identity and version of a file.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Fingerprint {
+    dev: u64,
+    ino: u64,
+    size: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
+impl Fingerprint {
+    /// Returns the fingerprint of the file or `None` if it can't be accessed.
+    fn of(path: &Path) -> Option<Self> {
+        use std::os::unix::fs::MetadataExt;
+
+        let metadata = std::fs::metadata(path).ok()?;
+        Some(Fingerprint {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            size: metadata.size(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+        })
+    }
+}
+
+/// File a zone was loaded from along with its state at the time of loading.
+#[derive(Debug, Clone)]
+pub(crate) struct Watched {
+    path: Option<PathBuf>,
+    fingerprint: Option<Fingerprint>,
+    last_check: Instant,
+}
+
+impl Watched {
+    /// Starts watching the file from which the zone with `name` (the value of `TZ`) is loaded.
+    pub(crate) fn new(name: Option<&std::ffi::OsStr>) -> Self {
+        let path = zoneinfo::zone_file_path(name);
+        let fingerprint = path.as_deref().and_then(Fingerprint::of);
+        Watched {
+            path,
+            fingerprint,
+            last_check: Instant::now(),
+        }
+    }
+
+    /// Returns `true` if the file should be checked according to the policy and marks it as
+    /// checked.
+    pub(crate) fn check_due(&mut self, policy: ReloadPolicy, now: Instant) -> bool {
+        match policy {
+            ReloadPolicy::Never => false,
+            ReloadPolicy::CheckEvery(interval) if now.duration_since(self.last_check) < interval => false,
+            ReloadPolicy::CheckEvery(_) => {
+                self.last_check = now;
+                true
+            },
+        }
+    }
+
+    /// Returns `true` if the file changed since the zone was loaded.
+    ///
+    /// This accesses the file system.
+    pub(crate) fn changed(&self) -> bool {
+        self.path.as_deref().and_then(Fingerprint::of) != self.fingerprint
+    }
+}
+
+struct LocalState {
+    policy: ReloadPolicy,
+    /// The value of `TZ` and the file of the local zone fingerprinted when it was loaded, `None`
+    /// if it wasn't loaded since the policy was set or since the file changed.
+    watched: Option<(Option<OsString>, Watched)>,
+}
+
+/// Avoids locking on every conversion if reloading is off.
+static LOCAL_ENABLED: AtomicBool = AtomicBool::new(false);
+
+static LOCAL: Mutex<LocalState> = Mutex::new(LocalState {
+    policy: ReloadPolicy::Never,
+    watched: None,
+});
+
+/// Sets when the local time zone is reloaded because its file changed.
+///
+/// The file is the one named by `TZ` or `/etc/localtime` if `TZ` is not set. Changing `TZ`
+/// always reloads the zone regardless of this policy.
+/// A zone set by [`set_local_zone`](crate::set_local_zone) is never reloaded.
+///
+/// The zone is loaded again on the next conversion so that its file is watched from the moment
+/// it's read.
+pub fn set_reload_policy(policy: ReloadPolicy) {
+    let mut local = LOCAL.lock().unwrap_or_else(PoisonError::into_inner);
+    local.policy = policy;
+    local.watched = None;
+    LOCAL_ENABLED.store(policy != ReloadPolicy::Never, Ordering::Relaxed);
+    drop(local);
+    if policy != ReloadPolicy::Never {
+        local::invalidate();
+    }
+}
+
+/// Returns the policy set by [`set_reload_policy`].
+pub fn reload_policy() -> ReloadPolicy {
+    LOCAL.lock().unwrap_or_else(PoisonError::into_inner).policy
+}
+
+/// Makes the local zone load again if its file changed and the policy says it's time
+/// to check.
+///
+/// If another thread is checking at the same time this returns immediately.
+pub(crate) fn check_local() {
+    if !LOCAL_ENABLED.load(Ordering::Relaxed) || local::local_zone_source() != LocalZoneSource::Environment {
+        return;
+    }
+    let mut local = match LOCAL.try_lock() {
+        Ok(local) => local,
+        Err(std::sync::TryLockError::Poisoned(error)) => error.into_inner(),
+        Err(std::sync::TryLockError::WouldBlock) => return,
+    };
+    let policy = local.policy;
+    match &mut local.watched {
+        Some((tz, watched)) => {
+            // if TZ changed the zone is loaded again anyway
+            if !watched.check_due(policy, Instant::now()) || !env::var_eq("TZ", tz.as_deref()) || !watched.changed() {
+                return;
+            }
+        },
+        // recorded by the next load
+        None => return,
+    }
+    local.watched = None;
+    drop(local);
+    local::invalidate();
+    notify(Reloaded::LocalZone);
+}
+
+/// Starts watching the file of the local zone for `TZ` if the policy checks it.
+///
+/// Called before loading the zone so that changes during loading are noticed.
+pub(crate) fn watch_local(tz: Option<&OsStr>) -> Option<Watched> {
+    if LOCAL_ENABLED.load(Ordering::Relaxed) {
+        Some(Watched::new(tz))
+    } else {
+        None
+    }
+}
+
+/// Records the file of the local zone loaded for `TZ`.
+pub(crate) fn local_loaded(tz: Option<&OsStr>, watched: Watched) {
+    let mut local = LOCAL.lock().unwrap_or_else(PoisonError::into_inner);
+    if local.policy != ReloadPolicy::Never {
+        local.watched = Some((tz.map(ToOwned::to_owned), watched));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use super::{ReloadPolicy, Reloaded};
+    use crate::ZoneCache;
+
+    #[test]
+    fn cached_zone() {
+        let _globals = crate::lock_globals();
+        let dir = std::env::temp_dir().join(format!("rl_localtime-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Zone");
+        let name = path.to_str().unwrap().to_owned();
+        std::fs::write(&path, crate::tzif::fixed(3600, "ABC")).unwrap();
+
+        let reloaded = Arc::new(Mutex::new(Vec::new()));
+        let hook_reloaded = Arc::clone(&reloaded);
+        super::set_reload_hook(move |zone| {
+            if let Reloaded::CachedZone(name) = zone {
+                hook_reloaded.lock().unwrap().push(name.to_owned());
+            }
+        });
+
+        let cache = ZoneCache::new(4);
+        cache.set_reload_policy(ReloadPolicy::CheckEvery(Duration::from_secs(0)));
+        assert_eq!(cache.get(&name).unwrap().to_local(0).unwrap().offset(), 3600);
+        assert_eq!(cache.get(&name).unwrap().to_local(0).unwrap().offset(), 3600);
+        assert!(reloaded.lock().unwrap().is_empty());
+
+        // replace the file the same way package managers do
+        std::fs::write(dir.join("Zone.new"), crate::tzif::fixed(7200, "XYZ")).unwrap();
+        std::fs::rename(dir.join("Zone.new"), &path).unwrap();
+        let zone = cache.get(&name).unwrap();
+        assert_eq!(zone.to_local(0).unwrap().offset(), 7200);
+        assert_eq!(zone.to_local(0).unwrap().abbreviation(), Some("XYZ"));
+        assert_eq!(*reloaded.lock().unwrap(), std::slice::from_ref(&name));
+
+        // loaded without checking, watched from the first check on
+        let cache = ZoneCache::new(4);
+        assert_eq!(cache.get(&name).unwrap().to_local(0).unwrap().offset(), 7200);
+        cache.set_reload_policy(ReloadPolicy::CheckEvery(Duration::from_secs(0)));
+        assert_eq!(cache.get(&name).unwrap().to_local(0).unwrap().offset(), 7200);
+        std::fs::write(dir.join("Zone.new"), crate::tzif::fixed(3600, "ABC")).unwrap();
+        std::fs::rename(dir.join("Zone.new"), &path).unwrap();
+        assert_eq!(cache.get(&name).unwrap().to_local(0).unwrap().offset(), 3600);
+        assert_eq!(*reloaded.lock().unwrap(), [name.clone(), name]);
+
+        super::clear_reload_hook();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn local_zone() {
+        let _globals = crate::lock_globals();
+        let dir = std::env::temp_dir().join(format!("rl_localtime-reload-local-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Zone");
+        std::fs::write(&path, crate::tzif::fixed(3600, "ABC")).unwrap();
+
+        let reloaded = Arc::new(Mutex::new(Vec::new()));
+        let hook_reloaded = Arc::clone(&reloaded);
+        super::set_reload_hook(move |zone| hook_reloaded.lock().unwrap().push(zone == Reloaded::LocalZone));
+        crate::set_env_source(crate::StaticEnv::new().with("TZ", &path));
+        super::set_reload_policy(ReloadPolicy::CheckEvery(Duration::from_secs(0)));
+        // loads the zone without checking the file
+        assert!(matches!(crate::local_zone_origin().unwrap(), crate::ZoneOrigin::File(_)));
+
+        // replaced right after loading, before any check
+        std::fs::write(dir.join("Zone.new"), crate::tzif::fixed(7200, "XYZ")).unwrap();
+        std::fs::rename(dir.join("Zone.new"), &path).unwrap();
+        assert_eq!(crate::localtime(0).unwrap().offset(), 7200);
+        assert_eq!(crate::localtime(0).unwrap().abbreviation(), Some("XYZ"));
+        assert_eq!(*reloaded.lock().unwrap(), [true]);
+
+        super::set_reload_policy(ReloadPolicy::Never);
+        super::clear_reload_hook();
+        crate::reset_env_source();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,197 @@
+//! Resolution of repeated and skipped local times.
+//!
+//! The C `mktime` silently picks one of the candidates steered only by `tm_isdst`. The code here
+//! finds all of them using only `localtime`-like conversion so it works the same way for the
+//! global local time zone and for [`TimeZone`](crate::TimeZone).
+
+use std::convert::TryFrom;
+use libc::time_t;
+use crate::{LocalDateTime, TzError};
+
+const SECS_PER_DAY: i64 = 86400;
+
+/// Result of converting local time to Unix time.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum LocalResult<T> {
+    /// The local time occurs exactly once.
+    Single(T),
+    /// The local time is repeated, usually because DST ended. Contains the earlier and the later
+    /// instant.
+    Ambiguous(T, T),
+    /// The local time was skipped, usually because DST started. Contains the last instant before
+    /// the skipped interval and the first instant after it.
+    Gap(T, T),
+}
+
+impl<T> LocalResult<T> {
+    /// Returns the value if the local time is not ambiguous and exists.
+    pub fn single(self) -> Option<T> {
+        match self {
+            LocalResult::Single(value) => Some(value),
+            LocalResult::Ambiguous(_, _) | LocalResult::Gap(_, _) => None,
+        }
+    }
+}
+
+/// Policy for resolving local time that is repeated or skipped.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Disambiguation {
+    /// Picks the earlier instant of repeated local time and the last instant before a gap.
+    Earliest,
+    /// Picks the later instant of repeated local time and the first instant after a gap.
+    Latest,
+    /// Returns an error if the local time is repeated or skipped.
+    Reject,
+    /// Picks the earlier instant of repeated local time and moves the local time in a gap forward
+    /// by the length of the gap (so 02:30 becomes 03:30 when clocks jump from 02:00 to 03:00).
+    ShiftForward,
+}
+
+/// Converts `i64` to `time_t` failing if it doesn't fit.
+#[allow(clippy::useless_conversion)]
+fn to_time_t(value: i64) -> Result<time_t, TzError> {
+    time_t::try_from(value).map_err(|_| TzError::Overflow)
+}
+
+#[allow(clippy::useless_conversion)]
+fn from_time_t(value: time_t) -> i64 {
+    i64::from(value)
+}
+
+/// Finds instants having the given local time using `to_local` to convert Unix time to local time.
+///
+/// Local times are compared in half-seconds so that a leap second (`23:59:60`) sorts between
+/// `23:59:59` and the following `00:00:00` instead of coinciding with the latter.
+pub(crate) struct Resolver<F> {
+    to_local: F,
+    local: i64,
+}
+
+/// Returns the local time in half-seconds since the epoch ignoring the offset.
+fn half_seconds(time: &LocalDateTime) -> i64 {
+    if time.is_leap_second() {
+        time.local_seconds() * 2 - 1
+    } else {
+        time.local_seconds() * 2
+    }
+}
+
+impl<F: FnMut(time_t) -> Result<LocalDateTime, TzError>> Resolver<F> {
+    pub(crate) fn new(time: &LocalDateTime, to_local: F) -> Self {
+        Resolver {
+            to_local,
+            local: half_seconds(time),
+        }
+    }
+
+    /// Returns the local time at `time` in half-seconds since the epoch ignoring the offset.
+    ///
+    /// Using this rather than the offset also handles zones with leap seconds correctly.
+    fn wall(&mut self, time: i64) -> Result<i64, TzError> {
+        Ok(half_seconds(&(self.to_local)(to_time_t(time)?)?))
+    }
+
+    pub(crate) fn resolve(&mut self) -> Result<LocalResult<time_t>, TzError> {
+        let local = self.local;
+        let before = (local / 2).checked_sub(2 * SECS_PER_DAY).ok_or(TzError::Overflow)?;
+        let after = (local / 2).checked_add(2 * SECS_PER_DAY).ok_or(TzError::Overflow)?;
+        let mut offsets = vec![self.wall(before)? - 2 * before, self.wall(after)? - 2 * after];
+        let mut found = Vec::new();
+        let mut i = 0;
+        // Each candidate offset either matches or reveals the offset in effect at the instant it
+        // points to. Zones don't change often so a few rounds is enough. Rounding up makes a leap
+        // second the candidate for the offset in effect just before it.
+        while i < offsets.len() && i < 8 {
+            let candidate = (local - offsets[i] + 1).div_euclid(2);
+            let wall = self.wall(candidate)?;
+            let offset = wall - 2 * candidate;
+            if wall == local {
+                found.push(candidate);
+            } else if !offsets.contains(&offset) {
+                offsets.push(offset);
+            }
+            i += 1;
+        }
+        found.sort_unstable();
+        found.dedup();
+
+        match (found.first(), found.last()) {
+            (Some(&first), Some(&last)) if first == last => Ok(LocalResult::Single(to_time_t(first)?)),
+            (Some(&first), Some(&last)) => Ok(LocalResult::Ambiguous(to_time_t(first)?, to_time_t(last)?)),
+            _ => {
+                let (before, after) = self.find_gap(&offsets)?;
+                Ok(LocalResult::Gap(to_time_t(before)?, to_time_t(after)?))
+            },
+        }
+    }
+
+    /// Finds the last instant with local time before the requested one and the first instant
+    /// after it.
+    fn find_gap(&mut self, offsets: &[i64]) -> Result<(i64, i64), TzError> {
+        let local = self.local;
+        let mut lo = (local - offsets.iter().max().expect("there are always two offsets")).div_euclid(2);
+        let mut hi = (local - offsets.iter().min().expect("there are always two offsets") + 1).div_euclid(2);
+        while self.wall(lo)? >= local {
+            lo = lo.checked_sub(SECS_PER_DAY).ok_or(TzError::Overflow)?;
+        }
+        while self.wall(hi)? <= local {
+            hi = hi.checked_add(SECS_PER_DAY).ok_or(TzError::Overflow)?;
+        }
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            if self.wall(mid)? < local {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        Ok((lo, hi))
+    }
+
+    pub(crate) fn resolve_with(&mut self, policy: Disambiguation) -> Result<time_t, TzError> {
+        match (self.resolve()?, policy) {
+            (LocalResult::Single(time), _) => Ok(time),
+            (LocalResult::Ambiguous(_, _), Disambiguation::Reject) => Err(TzError::Ambiguous),
+            (LocalResult::Gap(_, _), Disambiguation::Reject) => Err(TzError::Nonexistent),
+            (LocalResult::Ambiguous(earlier, _), Disambiguation::Earliest)
+            | (LocalResult::Ambiguous(earlier, _), Disambiguation::ShiftForward) => Ok(earlier),
+            (LocalResult::Ambiguous(_, later), Disambiguation::Latest) => Ok(later),
+            (LocalResult::Gap(before, _), Disambiguation::Earliest) => Ok(before),
+            (LocalResult::Gap(_, after), Disambiguation::Latest) => Ok(after),
+            (LocalResult::Gap(before, after), Disambiguation::ShiftForward) => {
+                // a skipped leap second is shifted to the following second
+                let skipped = (self.local - self.wall(from_time_t(before))? - 1).div_euclid(2);
+                to_time_t(from_time_t(after) + skipped)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LocalResult, Disambiguation};
+    use crate::{LocalDateTime, TimeZone, TzError};
+
+    #[test]
+    fn gaps_and_repeats() {
+        let zone = TimeZone::load("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+
+        // clocks jumped from 02:00 CET to 03:00 CEST at 2021-03-28 01:00:00 UTC
+        let skipped = LocalDateTime::new(2021, 3, 28, 2, 30, 0).unwrap();
+        assert_eq!(zone.resolve_local(skipped).unwrap(), LocalResult::Gap(1616893199, 1616893200));
+        assert_eq!(zone.from_local_with(skipped, Disambiguation::Earliest).unwrap(), 1616893199);
+        assert_eq!(zone.from_local_with(skipped, Disambiguation::Latest).unwrap(), 1616893200);
+        assert_eq!(zone.from_local_with(skipped, Disambiguation::ShiftForward).unwrap(), 1616893200 + 1800);
+        assert!(matches!(zone.from_local_with(skipped, Disambiguation::Reject), Err(TzError::Nonexistent)));
+
+        // clocks went back from 03:00 CEST to 02:00 CET at 2021-10-31 01:00:00 UTC
+        let repeated = LocalDateTime::new(2021, 10, 31, 2, 30, 0).unwrap();
+        assert_eq!(zone.resolve_local(repeated).unwrap(), LocalResult::Ambiguous(1635640200, 1635643800));
+        assert_eq!(zone.from_local_with(repeated, Disambiguation::Earliest).unwrap(), 1635640200);
+        assert_eq!(zone.from_local_with(repeated, Disambiguation::Latest).unwrap(), 1635643800);
+        assert!(matches!(zone.from_local_with(repeated, Disambiguation::Reject), Err(TzError::Ambiguous)));
+
+        let normal = LocalDateTime::new(2021, 7, 1, 2, 0, 0).unwrap();
+        assert_eq!(zone.resolve_local(normal).unwrap(), LocalResult::Single(1625097600));
+    }
+}
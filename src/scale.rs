@@ -0,0 +1,256 @@
+//! Conversions between POSIX time, UTC counting leap seconds, TAI and GPS time.
+//!
+//! UTC counting leap seconds, TAI and GPS time differ by constant offsets. Only converting to or
+//! from POSIX time needs the leap seconds, so only these conversions fail past the expiration of
+//! the table.
+
+use std::convert::TryFrom;
+use libc::time_t;
+use crate::{LeapSecond, LeapTable, TzError};
+
+/// TAI - GPS time.
+const TAI_GPS_OFFSET: i64 = 19;
+
+/// POSIX time of the GPS epoch, 1980-01-06 00:00:00 UTC.
+const GPS_EPOCH: i64 = 315_964_800;
+
+const SECS_PER_WEEK: i64 = 7 * 86400;
+
+/// Time scale of a timestamp, see [`LeapTable::convert`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum TimeScale {
+    /// POSIX (Unix) time which ignores leap seconds, so every day has 86400 seconds.
+    Posix,
+    /// Seconds since 1970-01-01 00:00:00 UTC counting leap seconds.
+    ///
+    /// This is the time used by zones from `right/`.
+    Utc,
+    /// Seconds since 1970-01-01 00:00:00 TAI.
+    ///
+    /// TAI - UTC before the first leap second is taken from the table, see
+    /// [`LeapTable::initial_tai_utc`].
+    Tai,
+    /// Seconds since the GPS epoch, 1980-01-06 00:00:00 UTC.
+    ///
+    /// GPS time doesn't count leap seconds and is 19 seconds behind TAI.
+    Gps,
+}
+
+/// GPS time as a week number and seconds into the week.
+///
+/// The week number counts from the GPS epoch and doesn't roll over.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct GpsWeekTime {
+    week: u32,
+    time_of_week: u32,
+}
+
+impl GpsWeekTime {
+    /// Creates the value, `None` if `time_of_week` is not less than 604800.
+    pub fn new(week: u32, time_of_week: u32) -> Option<Self> {
+        if i64::from(time_of_week) < SECS_PER_WEEK {
+            Some(GpsWeekTime { week, time_of_week })
+        } else {
+            None
+        }
+    }
+
+    /// Splits seconds since the GPS epoch, failing if the time is before the epoch.
+    pub fn from_gps(gps: time_t) -> Result<Self, TzError> {
+        #[allow(clippy::useless_conversion)]
+        let gps = i64::from(gps);
+        let week = u32::try_from(gps.div_euclid(SECS_PER_WEEK)).map_err(|_| TzError::Overflow)?;
+        let time_of_week = gps.rem_euclid(SECS_PER_WEEK) as u32;
+        Ok(GpsWeekTime { week, time_of_week })
+    }
+
+    /// Returns the number of seconds since the GPS epoch.
+    pub fn to_gps(&self) -> Result<time_t, TzError> {
+        to_time_t(i64::from(self.week) * SECS_PER_WEEK + i64::from(self.time_of_week))
+    }
+
+    /// Returns the week number.
+    pub fn week(&self) -> u32 {
+        self.week
+    }
+
+    /// Returns the number of seconds since the start of the week (Sunday 00:00:00 GPS time).
+    pub fn time_of_week(&self) -> u32 {
+        self.time_of_week
+    }
+}
+
+impl LeapTable {
+    /// Converts `t` from one time scale to another.
+    ///
+    /// A positive leap second has the same POSIX time as the second before it, so converting
+    /// such POSIX time returns the earlier one. Converting to or from POSIX time fails with
+    /// [`TzError::Expired`] if the time is at or after [`expires`](Self::expires).
+    pub fn convert(&self, t: time_t, from: TimeScale, to: TimeScale) -> Result<time_t, TzError> {
+        if from == to {
+            return Ok(t);
+        }
+        #[allow(clippy::useless_conversion)]
+        let t = i64::from(t);
+        let utc = match from {
+            TimeScale::Posix => {
+                self.check_expiry(t)?;
+                posix_to_utc(self.leap_seconds(), t)?
+            },
+            TimeScale::Utc => t,
+            TimeScale::Tai => t.checked_sub(self.initial_tai_utc()).ok_or(TzError::Overflow)?,
+            TimeScale::Gps => t.checked_add(gps_offset(self.initial_tai_utc())?).ok_or(TzError::Overflow)?,
+        };
+        let result = match to {
+            TimeScale::Posix => {
+                let posix = utc.checked_sub(correction(self.leap_seconds(), utc)).ok_or(TzError::Overflow)?;
+                self.check_expiry(posix)?;
+                posix
+            },
+            TimeScale::Utc => utc,
+            TimeScale::Tai => utc.checked_add(self.initial_tai_utc()).ok_or(TzError::Overflow)?,
+            TimeScale::Gps => utc.checked_sub(gps_offset(self.initial_tai_utc())?).ok_or(TzError::Overflow)?,
+        };
+        to_time_t(result)
+    }
+
+    pub(crate) fn check_expiry(&self, posix: i64) -> Result<(), TzError> {
+        match self.expires() {
+            #[allow(clippy::useless_conversion)]
+            Some(expires) if posix >= i64::from(expires) => Err(TzError::Expired),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Returns UTC counting leap seconds minus GPS time.
+fn gps_offset(initial_tai_utc: i64) -> Result<i64, TzError> {
+    // GPS time was equal to UTC at its epoch, so the leap seconds before it are TAI - GPS time
+    // minus the initial TAI - UTC
+    (GPS_EPOCH + TAI_GPS_OFFSET).checked_sub(initial_tai_utc).ok_or(TzError::Overflow)
+}
+
+#[allow(clippy::useless_conversion)]
+pub(crate) fn to_time_t(value: i64) -> Result<time_t, TzError> {
+    time_t::try_from(value).map_err(|_| TzError::Overflow)
+}
+
+/// Returns the total correction at `t` counting leap seconds (`leapcorr` in C).
+#[allow(clippy::useless_conversion)]
+fn correction(leap_seconds: &[LeapSecond], t: i64) -> i64 {
+    leap_seconds.iter()
+        .rev()
+        .find(|leap| t >= i64::from(leap.at()))
+        .map_or(0, LeapSecond::correction)
+}
+
+/// Converts POSIX time to UTC counting leap seconds the same way as `posix2time_z` in C.
+fn posix_to_utc(leap_seconds: &[LeapSecond], t: i64) -> Result<i64, TzError> {
+    let to_posix = |x: i64| x.checked_sub(correction(leap_seconds, x)).ok_or(TzError::Overflow);
+    let mut x = t.checked_add(correction(leap_seconds, t)).ok_or(TzError::Overflow)?;
+    let mut y = to_posix(x)?;
+    // a removed leap second doesn't exist so the adjacent second is returned
+    if y < t {
+        while y < t {
+            x = x.checked_add(1).ok_or(TzError::Overflow)?;
+            y = to_posix(x)?;
+        }
+        if y != t {
+            x -= 1;
+        }
+    } else if y > t {
+        while y > t {
+            x = x.checked_sub(1).ok_or(TzError::Overflow)?;
+            y = to_posix(x)?;
+        }
+        if y != t {
+            x += 1;
+        }
+    }
+    Ok(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GpsWeekTime, TimeScale};
+    use crate::{LeapTable, TimeZone, TzError};
+
+    const LEAPSECONDS: &str = "\
+Leap\t1972\tJun\t30\t23:59:60\t+\tS
+Leap\t1972\tDec\t31\t23:59:60\t+\tS
+Leap\t1973\tDec\t31\t23:59:60\t+\tS
+Leap\t1974\tDec\t31\t23:59:60\t+\tS
+Leap\t1975\tDec\t31\t23:59:60\t+\tS
+Leap\t1976\tDec\t31\t23:59:60\t+\tS
+Leap\t1977\tDec\t31\t23:59:60\t+\tS
+Leap\t1978\tDec\t31\t23:59:60\t+\tS
+Leap\t1979\tDec\t31\t23:59:60\t+\tS
+Leap\t1981\tJun\t30\t23:59:60\t+\tS
+Leap\t1982\tJun\t30\t23:59:60\t+\tS
+Leap\t1983\tJun\t30\t23:59:60\t+\tS
+Leap\t1985\tJun\t30\t23:59:60\t+\tS
+Leap\t1987\tDec\t31\t23:59:60\t+\tS
+Leap\t1989\tDec\t31\t23:59:60\t+\tS
+Leap\t1990\tDec\t31\t23:59:60\t+\tS
+Leap\t1992\tJun\t30\t23:59:60\t+\tS
+Leap\t1993\tJun\t30\t23:59:60\t+\tS
+Leap\t1994\tJun\t30\t23:59:60\t+\tS
+Leap\t1995\tDec\t31\t23:59:60\t+\tS
+Leap\t1997\tJun\t30\t23:59:60\t+\tS
+Leap\t1998\tDec\t31\t23:59:60\t+\tS
+Leap\t2005\tDec\t31\t23:59:60\t+\tS
+Leap\t2008\tDec\t31\t23:59:60\t+\tS
+Leap\t2012\tJun\t30\t23:59:60\t+\tS
+Leap\t2015\tJun\t30\t23:59:60\t+\tS
+Leap\t2016\tDec\t31\t23:59:60\t+\tS
+#Expires 2026\tJun\t28\t00:00:00
+";
+
+    #[test]
+    fn time_scales() {
+        let table = LeapTable::parse_leapseconds(LEAPSECONDS).unwrap();
+        // 2017-01-01 00:00:00 UTC, after the last leap second
+        let posix = 1483228800;
+        assert_eq!(table.convert(posix, TimeScale::Posix, TimeScale::Utc).unwrap(), posix + 27);
+        assert_eq!(table.convert(posix, TimeScale::Posix, TimeScale::Tai).unwrap(), posix + 37);
+        let gps = table.convert(posix, TimeScale::Posix, TimeScale::Gps).unwrap();
+        let week = GpsWeekTime::from_gps(gps).unwrap();
+        assert_eq!((week.week(), week.time_of_week()), (1930, 18));
+        assert_eq!(week.to_gps().unwrap(), gps);
+        assert_eq!(table.convert(gps, TimeScale::Gps, TimeScale::Posix).unwrap(), posix);
+        assert_eq!(table.convert(315964800, TimeScale::Posix, TimeScale::Gps).unwrap(), 0);
+
+        // 2016-12-31 23:59:60 UTC
+        assert_eq!(table.convert(posix + 26, TimeScale::Utc, TimeScale::Posix).unwrap(), posix - 1);
+        assert_eq!(table.convert(posix - 1, TimeScale::Posix, TimeScale::Utc).unwrap(), posix + 25);
+
+        // 2026-06-28 00:00:00 UTC
+        let expires = 1782604800;
+        assert_eq!(table.expires(), Some(expires));
+        assert!(table.convert(expires - 1, TimeScale::Posix, TimeScale::Tai).is_ok());
+        assert!(matches!(table.convert(expires, TimeScale::Posix, TimeScale::Tai), Err(TzError::Expired)));
+        let tai = table.convert(expires - 1, TimeScale::Posix, TimeScale::Tai).unwrap() + 1;
+        assert!(matches!(table.convert(tai, TimeScale::Tai, TimeScale::Posix), Err(TzError::Expired)));
+        assert!(table.convert(tai, TimeScale::Tai, TimeScale::Gps).is_ok());
+
+        assert!(matches!(GpsWeekTime::from_gps(-1), Err(TzError::Overflow)));
+
+        // TAI - UTC before the first leap second comes from leap-seconds.list
+        assert_eq!(table.initial_tai_utc(), 10);
+        let list = LeapTable::parse_leap_seconds_list("2272060800\t12\n2287785600\t13\n").unwrap();
+        assert_eq!(list.initial_tai_utc(), 12);
+        assert_eq!(list.convert(0, TimeScale::Utc, TimeScale::Tai).unwrap(), 12);
+        assert_eq!(list.convert(posix, TimeScale::Posix, TimeScale::Tai).unwrap(), posix + 13);
+        // GPS time is still 19 seconds behind TAI
+        let tai = list.convert(315964800, TimeScale::Posix, TimeScale::Tai).unwrap();
+        assert_eq!(list.convert(tai, TimeScale::Tai, TimeScale::Gps).unwrap(), tai - 19 - 315964800);
+        assert_eq!(list.convert(tai - 19 - 315964800, TimeScale::Gps, TimeScale::Tai).unwrap(), tai);
+        assert_eq!(GpsWeekTime::new(0, 604800), None);
+
+        let zone = TimeZone::utc().unwrap().with_leap_table(&table).unwrap();
+        assert_eq!(zone.leap_seconds().count(), 28);
+        assert_eq!(zone.leap_table(), table);
+        assert_eq!(TimeZone::utc().unwrap().leap_table().leap_seconds(), []);
+    }
+}
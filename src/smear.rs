@@ -0,0 +1,285 @@
+//! Leap second smearing.
+//!
+//! Instead of inserting or removing a leap second some NTP servers slow down or speed up the
+//! clocks of their clients for a while around it, so the clients never see second 60 but their
+//! POSIX time is off by up to a second during the smear. The conversions here translate between
+//! such smeared time and the time scales of [`TimeScale`].
+//!
+//! The smear is a fraction of a second so the times are in nanoseconds, limiting them to years
+//! 1678 to 2261.
+
+use crate::{LeapTable, TimeScale, TzError};
+
+const NANOS_PER_SEC: i64 = 1_000_000_000;
+
+/// Smears longer than this could overlap for leap seconds half a year apart.
+const MAX_WINDOW: u32 = 30 * 86400;
+
+/// How the smeared clock deviates from POSIX time during the smear.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum SmearShape {
+    /// The clock runs at a constant slower or faster rate.
+    Linear,
+    /// The rate changes gradually, following half a period of cosine.
+    Cosine,
+}
+
+impl SmearShape {
+    /// Returns the part of the leap second applied when `x` of the window elapsed.
+    fn applied(self, x: f64) -> f64 {
+        match self {
+            SmearShape::Linear => x,
+            SmearShape::Cosine => (1.0 - (std::f64::consts::PI * x).cos()) / 2.0,
+        }
+    }
+}
+
+/// Window and shape of a leap second smear.
+///
+/// The window is given in seconds of the smeared clock before and after the end of the day
+/// containing the leap second. During the window the smeared clock shows POSIX time shifted by
+/// the part of the leap second applied so far.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct LeapSmear {
+    before: u32,
+    after: u32,
+    shape: SmearShape,
+}
+
+impl LeapSmear {
+    /// Creates the smear, `None` if the window is shorter than two seconds or longer than 30
+    /// days.
+    pub fn new(before: u32, after: u32, shape: SmearShape) -> Option<Self> {
+        match before.checked_add(after) {
+            Some(window) if (2..=MAX_WINDOW).contains(&window) => Some(LeapSmear { before, after, shape }),
+            _ => None,
+        }
+    }
+
+    /// Returns the linear smear from noon before the leap second to noon after it used by
+    /// public NTP services.
+    pub fn noon_to_noon() -> Self {
+        LeapSmear {
+            before: 43200,
+            after: 43200,
+            shape: SmearShape::Linear,
+        }
+    }
+
+    /// Returns the number of seconds the smear starts before the end of the day.
+    pub fn before(&self) -> u32 {
+        self.before
+    }
+
+    /// Returns the number of seconds the smear ends after the end of the day.
+    pub fn after(&self) -> u32 {
+        self.after
+    }
+
+    /// Returns the shape of the smear.
+    pub fn shape(&self) -> SmearShape {
+        self.shape
+    }
+}
+
+/// Smear of a single leap second in nanoseconds.
+struct Window {
+    /// Start in UTC counting leap seconds.
+    start: i64,
+    /// Start in smeared time.
+    smeared_start: i64,
+    /// Length in smeared time.
+    len: i64,
+    /// The change of the correction, positive for inserted leap seconds.
+    change: i64,
+    /// The correction before the leap second.
+    previous: i64,
+}
+
+impl Window {
+    /// Returns the length in UTC counting leap seconds.
+    fn utc_len(&self) -> i64 {
+        self.len + self.change * NANOS_PER_SEC
+    }
+
+    fn correction_after(&self) -> i64 {
+        self.previous + self.change
+    }
+
+    /// Returns the part of the smear elapsed at `utc`.
+    fn elapsed(&self, utc: i64) -> f64 {
+        (utc - self.start) as f64 / self.utc_len() as f64
+    }
+
+    /// Returns the UTC time at `smeared` which must be in the window.
+    fn unsmear(&self, smeared: i64, shape: SmearShape) -> i64 {
+        let target = (smeared - self.smeared_start) as f64;
+        let change = (self.change * NANOS_PER_SEC) as f64;
+        let len = self.utc_len() as f64;
+        // smeared time elapsed since the start is increasing in the part of the window elapsed
+        let (mut lo, mut hi) = (0.0f64, 1.0f64);
+        for _ in 0..64 {
+            let mid = (lo + hi) / 2.0;
+            if mid * len - change * shape.applied(mid) < target {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        self.start + (lo * len).round() as i64
+    }
+}
+
+/// Converts seconds to nanoseconds failing on overflow.
+fn nanos(secs: i64) -> Result<i64, TzError> {
+    secs.checked_mul(NANOS_PER_SEC).ok_or(TzError::Overflow)
+}
+
+impl LeapTable {
+    /// Returns the smear windows of the leap seconds in this table.
+    fn smear_windows(&self, smear: &LeapSmear) -> Result<Vec<Window>, TzError> {
+        let mut windows = Vec::new();
+        let mut previous = 0;
+        for leap in self.leap_seconds() {
+            let change = leap.correction() - previous;
+            if change != 0 {
+                #[allow(clippy::useless_conversion)]
+                let at = i64::from(leap.at());
+                // POSIX time of the end of the day: an inserted second starts at the old
+                // correction, a removed one is the last second of the day
+                let end_of_day = at.checked_sub(previous)
+                    .and_then(|end| end.checked_add(i64::from(change < 0)))
+                    .ok_or(TzError::Overflow)?;
+                let smeared_start = nanos(end_of_day.checked_sub(i64::from(smear.before)).ok_or(TzError::Overflow)?)?;
+                let start = smeared_start.checked_add(nanos(previous)?).ok_or(TzError::Overflow)?;
+                windows.push(Window {
+                    start,
+                    smeared_start,
+                    len: nanos(i64::from(smear.before) + i64::from(smear.after))?,
+                    change,
+                    previous,
+                });
+            }
+            previous = leap.correction();
+        }
+        Ok(windows)
+    }
+
+    /// Converts `t` in nanoseconds on time scale `from` to the time shown by a clock using
+    /// `smear`, in nanoseconds since the epoch.
+    ///
+    /// Outside of the smears the result is POSIX time. Fails with [`TzError::Expired`] if the
+    /// result is at or after [`expires`](Self::expires).
+    pub fn to_smeared(&self, t: i64, from: TimeScale, smear: &LeapSmear) -> Result<i64, TzError> {
+        let utc = self.nanos_to_utc(t, from)?;
+        let mut smeared = utc.checked_sub(nanos(self.leap_seconds().last().map_or(0, |leap| leap.correction()))?);
+        for window in self.smear_windows(smear)? {
+            if utc < window.start {
+                smeared = utc.checked_sub(nanos(window.previous)?);
+                break;
+            }
+            if utc - window.start < window.utc_len() {
+                let applied = window.change as f64 * smear.shape.applied(window.elapsed(utc));
+                let applied = (applied * NANOS_PER_SEC as f64).round() as i64;
+                smeared = utc.checked_sub(nanos(window.previous)? + applied);
+                break;
+            }
+        }
+        let smeared = smeared.ok_or(TzError::Overflow)?;
+        self.check_expiry(smeared.div_euclid(NANOS_PER_SEC))?;
+        Ok(smeared)
+    }
+
+    /// Converts the time in nanoseconds since the epoch shown by a clock using `smear` to time
+    /// scale `to`, in nanoseconds.
+    ///
+    /// This is the inverse of [`to_smeared`](Self::to_smeared) up to rounding. Fails with
+    /// [`TzError::Expired`] if `smeared` is at or after [`expires`](Self::expires).
+    pub fn from_smeared(&self, smeared: i64, to: TimeScale, smear: &LeapSmear) -> Result<i64, TzError> {
+        self.check_expiry(smeared.div_euclid(NANOS_PER_SEC))?;
+        let mut utc = None;
+        let mut correction = 0;
+        for window in self.smear_windows(smear)? {
+            if smeared < window.smeared_start {
+                break;
+            }
+            if smeared - window.smeared_start < window.len {
+                utc = Some(window.unsmear(smeared, smear.shape));
+                break;
+            }
+            correction = window.correction_after();
+        }
+        let utc = match utc {
+            Some(utc) => utc,
+            None => smeared.checked_add(nanos(correction)?).ok_or(TzError::Overflow)?,
+        };
+        self.utc_to_nanos(utc, to)
+    }
+
+    /// Converts nanoseconds on the time scale to nanoseconds of UTC counting leap seconds.
+    fn nanos_to_utc(&self, t: i64, from: TimeScale) -> Result<i64, TzError> {
+        let secs = self.convert(crate::scale::to_time_t(t.div_euclid(NANOS_PER_SEC))?, from, TimeScale::Utc)?;
+        #[allow(clippy::useless_conversion)]
+        nanos(i64::from(secs))?.checked_add(t.rem_euclid(NANOS_PER_SEC)).ok_or(TzError::Overflow)
+    }
+
+    /// Converts nanoseconds of UTC counting leap seconds to nanoseconds on the time scale.
+    fn utc_to_nanos(&self, utc: i64, to: TimeScale) -> Result<i64, TzError> {
+        let secs = self.convert(crate::scale::to_time_t(utc.div_euclid(NANOS_PER_SEC))?, TimeScale::Utc, to)?;
+        #[allow(clippy::useless_conversion)]
+        nanos(i64::from(secs))?.checked_add(utc.rem_euclid(NANOS_PER_SEC)).ok_or(TzError::Overflow)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LeapSmear, SmearShape, NANOS_PER_SEC};
+    use crate::{LeapTable, TimeScale, TzError};
+
+    #[test]
+    fn smears() {
+        let table = LeapTable::parse_leapseconds("\
+Leap\t2015\tJun\t30\t23:59:60\t+\tS
+Leap\t2016\tDec\t31\t23:59:60\t+\tS
+Leap\t2017\tJun\t30\t23:59:59\t-\tS
+#Expires 2018\tJan\t1\t00:00:00
+").unwrap();
+        let smear = LeapSmear::noon_to_noon();
+        // 2017-01-01 00:00:00 UTC
+        let midnight = 1483228800 * NANOS_PER_SEC;
+        // 23:59:60.5 is the middle of the smear
+        let utc = (1483228800 + 1) * NANOS_PER_SEC + NANOS_PER_SEC / 2;
+        assert_eq!(table.to_smeared(utc, TimeScale::Utc, &smear).unwrap(), midnight);
+        // 43199.5 / 86401 of the leap second was applied at 23:59:59.5
+        assert_eq!(table.to_smeared(midnight - NANOS_PER_SEC / 2, TimeScale::Posix, &smear).unwrap(), midnight - NANOS_PER_SEC + 11574);
+        // noon before and after
+        let before = midnight - 43200 * NANOS_PER_SEC;
+        let after = midnight + 43200 * NANOS_PER_SEC;
+        assert_eq!(table.to_smeared(before, TimeScale::Posix, &smear).unwrap(), before);
+        assert_eq!(table.to_smeared(after, TimeScale::Posix, &smear).unwrap(), after);
+        assert_eq!(table.from_smeared(midnight, TimeScale::Utc, &smear).unwrap(), utc);
+
+        let cosine = LeapSmear::new(1000, 1000, SmearShape::Cosine).unwrap();
+        for &smear in &[smear, cosine] {
+            for offset in (-50000..50000).step_by(997) {
+                let t = midnight + offset * NANOS_PER_SEC + 123_456_789;
+                let smeared = table.to_smeared(t, TimeScale::Tai, &smear).unwrap();
+                let back = table.from_smeared(smeared, TimeScale::Tai, &smear).unwrap();
+                assert!((back - t).abs() <= 1, "{} {} {}", t, smeared, back);
+                // TAI - UTC changes from 11 to 12 seconds
+                assert!((t - 12 * NANOS_PER_SEC..=t - 11 * NANOS_PER_SEC).contains(&smeared));
+            }
+        }
+
+        // the clock speeds up to catch up with the removed second at 2017-06-30 23:59:59
+        let midnight = 1498867200 * NANOS_PER_SEC;
+        let smeared = table.to_smeared(midnight, TimeScale::Posix, &cosine).unwrap();
+        assert!((midnight - NANOS_PER_SEC..midnight).contains(&smeared));
+        assert_eq!(table.from_smeared(smeared, TimeScale::Posix, &cosine).unwrap(), midnight);
+
+        assert!(matches!(table.to_smeared(1514764800 * NANOS_PER_SEC, TimeScale::Posix, &smear), Err(TzError::Expired)));
+        assert!(matches!(table.from_smeared(1514764800 * NANOS_PER_SEC, TimeScale::Posix, &smear), Err(TzError::Expired)));
+        assert_eq!(LeapSmear::new(1, 0, SmearShape::Linear), None);
+    }
+}
@@ -0,0 +1,141 @@
+//! Iteration over changes of UT offset, DST or abbreviation of a time zone.
+
+use std::ops::Bound;
+use libc::time_t;
+use crate::{ffi, Abbreviation, TimeZone};
+
+/// Change of UT offset, DST flag or abbreviation in a time zone.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Transition {
+    at: time_t,
+    offset_before: i32,
+    offset_after: i32,
+    is_dst: bool,
+    abbreviation: Abbreviation,
+}
+
+impl Transition {
+    /// Returns the Unix time at which the new rules take effect.
+    pub fn at(&self) -> time_t {
+        self.at
+    }
+
+    /// Returns the UT offset in seconds in effect before the transition.
+    pub fn offset_before(&self) -> i32 {
+        self.offset_before
+    }
+
+    /// Returns the UT offset in seconds in effect since the transition.
+    pub fn offset_after(&self) -> i32 {
+        self.offset_after
+    }
+
+    /// Returns whether DST is in effect since the transition.
+    pub fn is_dst(&self) -> bool {
+        self.is_dst
+    }
+
+    /// Returns the abbreviation in effect since the transition.
+    pub fn abbreviation(&self) -> &str {
+        self.abbreviation.as_str()
+    }
+}
+
+/// Iterator over the transitions of a time zone returned from [`TimeZone::transitions`].
+///
+/// Transitions that don't change anything observable are skipped. Transitions past the last one
+/// stored in the time zone are computed from the rules or by repeating the stored ones every 400
+/// years the same way conversions do.
+#[derive(Debug, Clone)]
+pub struct Transitions<'a> {
+    zone: &'a TimeZone,
+    /// The next transition is searched at or after this time, `None` when finished.
+    next: Option<time_t>,
+    end: Bound<time_t>,
+}
+
+impl<'a> Transitions<'a> {
+    pub(crate) fn new(zone: &'a TimeZone, start: Bound<time_t>, end: Bound<time_t>) -> Self {
+        let next = match start {
+            Bound::Included(start) => Some(start),
+            Bound::Excluded(start) => start.checked_add(1),
+            Bound::Unbounded => Some(time_t::MIN),
+        };
+        Transitions {
+            zone,
+            next,
+            end,
+        }
+    }
+
+    /// Returns the UT offset, DST flag and abbreviation of the type with index `i`.
+    fn zone_type(&self, i: libc::c_int) -> (i32, bool, Abbreviation) {
+        let mut offset = 0;
+        let mut is_dst = false;
+        let mut abbr = [0; crate::ABBR_BUF_SIZE];
+        unsafe {
+            ffi::rl_zone_type(self.zone.state(), i, &mut offset, &mut is_dst, abbr.as_mut_ptr(), abbr.len());
+        }
+        // The C code stores the offset in int_fast32_t.
+        (offset as i32, is_dst, Abbreviation::from_c(&abbr))
+    }
+}
+
+impl Iterator for Transitions<'_> {
+    type Item = Transition;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut at = self.next?;
+        let mut before = 0;
+        let mut after = 0;
+        if !unsafe { ffi::rl_next_transition(self.zone.state(), &mut at, &mut before, &mut after) } {
+            self.next = None;
+            return None;
+        }
+        let in_range = match self.end {
+            Bound::Included(end) => at <= end,
+            Bound::Excluded(end) => at < end,
+            Bound::Unbounded => true,
+        };
+        if !in_range {
+            self.next = None;
+            return None;
+        }
+        self.next = at.checked_add(1);
+
+        let (offset_before, _, _) = self.zone_type(before);
+        let (offset_after, is_dst, abbreviation) = self.zone_type(after);
+        Some(Transition {
+            at,
+            offset_before,
+            offset_after,
+            is_dst,
+            abbreviation,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::TimeZone;
+
+    #[test]
+    fn posix_rules() {
+        let zone = TimeZone::load("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        // 2021-01-01 00:00:00 UTC to 2022-01-01 00:00:00 UTC
+        let transitions = zone.transitions(1609459200..1640995200).collect::<Vec<_>>();
+        assert_eq!(transitions.len(), 2);
+        assert_eq!(transitions[0].at(), 1616893200);
+        assert_eq!(transitions[0].offset_before(), 3600);
+        assert_eq!(transitions[0].offset_after(), 7200);
+        assert!(transitions[0].is_dst());
+        assert_eq!(transitions[0].abbreviation(), "CEST");
+        assert_eq!(transitions[1].at(), 1635642000);
+        assert_eq!(transitions[1].offset_after(), 3600);
+        assert!(!transitions[1].is_dst());
+        assert_eq!(transitions[1].abbreviation(), "CET");
+
+        assert_eq!(zone.transitions(..=1640995200).last(), Some(transitions[1]));
+        assert_eq!(TimeZone::utc().unwrap().transitions(..).count(), 0);
+    }
+}
@@ -0,0 +1,108 @@
+//! Errors of parsing TZif data.
+
+use std::fmt;
+
+/// Part of TZif data that is invalid.
+///
+/// The sections are listed in the order in which they appear in the data. Files of version 2 and
+/// later contain them twice, the error doesn't distinguish which copy is invalid.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum TzifSection {
+    /// The magic or counts are invalid or the data is shorter than the counts require.
+    Header,
+    /// Transition times are not sorted.
+    TransitionTimes,
+    /// A transition refers to a nonexistent local time type.
+    TransitionTypes,
+    /// A local time type has invalid DST flag or abbreviation index.
+    LocalTimeTypes,
+    /// Leap second records are not sorted.
+    LeapSeconds,
+    /// A standard/wall indicator is neither 0 nor 1.
+    StandardWallIndicators,
+    /// A UT/local indicator is neither 0 nor 1.
+    UtLocalIndicators,
+}
+
+impl TzifSection {
+    /// Converts the value of `enum tzif_section` from C code.
+    pub(crate) fn from_c(section: libc::c_int) -> Option<Self> {
+        match section {
+            1 => Some(TzifSection::Header),
+            2 => Some(TzifSection::TransitionTimes),
+            3 => Some(TzifSection::TransitionTypes),
+            4 => Some(TzifSection::LocalTimeTypes),
+            5 => Some(TzifSection::LeapSeconds),
+            6 => Some(TzifSection::StandardWallIndicators),
+            7 => Some(TzifSection::UtLocalIndicators),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TzifSection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TzifSection::Header => write!(f, "header"),
+            TzifSection::TransitionTimes => write!(f, "transition times"),
+            TzifSection::TransitionTypes => write!(f, "transition types"),
+            TzifSection::LocalTimeTypes => write!(f, "local time types"),
+            TzifSection::LeapSeconds => write!(f, "leap seconds"),
+            TzifSection::StandardWallIndicators => write!(f, "standard/wall indicators"),
+            TzifSection::UtLocalIndicators => write!(f, "UT/local indicators"),
+        }
+    }
+}
+
+/// Error returned when TZif data passed to [`TimeZone::from_tzif`](crate::TimeZone::from_tzif)
+/// is invalid.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct TzifError {
+    section: TzifSection,
+}
+
+impl TzifError {
+    pub(crate) fn new(section: TzifSection) -> Self {
+        TzifError { section }
+    }
+
+    /// Returns the section of the data that is invalid.
+    pub fn section(&self) -> TzifSection {
+        self.section
+    }
+}
+
+impl fmt::Display for TzifError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid {} in TZif data", self.section)
+    }
+}
+
+impl std::error::Error for TzifError {}
+
+/// Builds version 1 TZif data of a zone with fixed UT offset.
+#[cfg(test)]
+pub(crate) fn fixed(offset: i32, abbreviation: &str) -> Vec<u8> {
+    fixed_with_leap_seconds(offset, abbreviation, &[])
+}
+
+/// Like [`fixed`] but with the given leap second transition times and corrections.
+#[cfg(test)]
+pub(crate) fn fixed_with_leap_seconds(offset: i32, abbreviation: &str, leap_seconds: &[(u32, i32)]) -> Vec<u8> {
+    let mut data = b"TZif".to_vec();
+    data.extend_from_slice(&[0; 16]);
+    // isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt
+    for &count in &[0, 0, leap_seconds.len() as u32, 0, 1, abbreviation.len() as u32 + 1] {
+        data.extend_from_slice(&count.to_be_bytes());
+    }
+    data.extend_from_slice(&offset.to_be_bytes());
+    data.extend_from_slice(&[0, 0]);
+    data.extend_from_slice(abbreviation.as_bytes());
+    data.push(0);
+    for &(at, correction) in leap_seconds {
+        data.extend_from_slice(&at.to_be_bytes());
+        data.extend_from_slice(&correction.to_be_bytes());
+    }
+    data
+}
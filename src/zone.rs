@@ -0,0 +1,361 @@
+//! Owned time zones independent of the `TZ` environment variable.
+
+use std::io;
+use std::convert::TryFrom;
+use std::ffi::CString;
+use std::ptr::NonNull;
+use std::ops::RangeBounds;
+use libc::{c_int, time_t};
+use crate::ffi;
+use crate::{LocalDateTime, TzError, LocalResult, Disambiguation, TzifError, TzifSection};
+use crate::resolve::Resolver;
+use crate::transition::Transitions;
+use crate::leap::{self, LeapSecond, LeapSeconds, LeapTable};
+
+/// Where a zone loaded by name came from (`enum zone_origin` in C).
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum ZoneKind {
+    /// The name was empty.
+    Utc,
+    /// The zone was loaded from a TZif file.
+    File,
+    /// The name was parsed as a POSIX TZ string.
+    Posix,
+}
+
+impl ZoneKind {
+    fn from_c(value: c_int) -> Option<Self> {
+        match value {
+            1 => Some(ZoneKind::Utc),
+            2 => Some(ZoneKind::File),
+            3 => Some(ZoneKind::Posix),
+            _ => None,
+        }
+    }
+}
+
+/// Parsed time zone.
+///
+/// As opposed to [`localtime`](crate::localtime) and [`mktime`](crate::mktime) this never looks
+/// at the environment or touches the global state of the C library. The zone is immutable once
+/// loaded so it can be shared between threads and used in parallel.
+pub struct TimeZone {
+    state: NonNull<ffi::State>,
+}
+
+impl TimeZone {
+    /// Loads the time zone with the given name.
+    ///
+    /// The name has the same format as the value of `TZ` environment variable: either a name of
+    /// a file in one of the [zoneinfo directories](crate::zoneinfo_path) (e.g.
+    /// `Europe/Bratislava`), an absolute path or a POSIX TZ string (e.g.
+    /// `CET-1CEST,M3.5.0,M10.5.0/3`). Empty string means UTC.
+    pub fn load(name: &str) -> Result<Self, TzError> {
+        Self::load_tz(Some(name.as_ref())).map(|(zone, _)| zone)
+    }
+
+    /// Parses the time zone from the contents of a TZif file.
+    ///
+    /// Versions 1 to 4 are supported and the data is validated the same way as when loading a
+    /// file, so this can be used with zones embedded in the binary or stored elsewhere.
+    pub fn from_tzif(data: &[u8]) -> Result<Self, TzError> {
+        let mut section = 0;
+        unsafe {
+            if let Some(state) = NonNull::new(ffi::rl_tzalloc_tzif(data.as_ptr().cast(), data.len(), &mut section)) {
+                return Ok(TimeZone { state });
+            }
+        }
+        // errno has to be read before anything else can overwrite it
+        let error = io::Error::last_os_error();
+        match TzifSection::from_c(section) {
+            Some(section) => Err(TzifError::new(section).into()),
+            None => Err(TzError::ZoneLoad(error)),
+        }
+    }
+
+    /// Loads the zone for the given value of `TZ`, `None` meaning the system default.
+    pub(crate) fn load_tz(tz: Option<&std::ffi::OsStr>) -> Result<(Self, ZoneKind), TzError> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = tz
+            .map(|tz| CString::new(tz.as_bytes()))
+            .transpose()
+            .map_err(|_| TzError::ZoneLoad(io::Error::new(io::ErrorKind::InvalidInput, "time zone name contains null byte")))?;
+        let name = name.as_deref().map_or(std::ptr::null(), std::ffi::CStr::as_ptr);
+        let mut kind = 0;
+        let zone = unsafe {
+            NonNull::new(ffi::rl_tzalloc(name, &mut kind))
+                .map(|state| TimeZone { state })
+                .ok_or_else(|| TzError::ZoneLoad(io::Error::last_os_error()))?
+        };
+        let kind = ZoneKind::from_c(kind).expect("rl_tzalloc reports the origin on success");
+        Ok((zone, kind))
+    }
+
+    pub(crate) fn state(&self) -> *const ffi::State {
+        self.state.as_ptr()
+    }
+
+    /// Returns UTC time zone.
+    ///
+    /// This is the same as loading an empty name but never touches the file system.
+    pub fn utc() -> Result<Self, TzError> {
+        Self::load("")
+    }
+
+    /// Converts Unix time to calendar time in this time zone.
+    ///
+    /// An inserted leap second is returned as second 60 if the zone has leap seconds.
+    pub fn to_local(&self, sec: time_t) -> Result<LocalDateTime, TzError> {
+        let mut abbr = [0; crate::ABBR_BUF_SIZE];
+        let tm = unsafe {
+            let mut out = std::mem::zeroed();
+            // no locking is involved so the only possible error is EOVERFLOW
+            if ffi::rl_localtime_rz(self.state.as_ptr(), &sec, &mut out, abbr.as_mut_ptr(), abbr.len()).is_null() {
+                return Err(TzError::Overflow);
+            }
+            out
+        };
+        crate::tm_to_local(tm, &abbr)
+    }
+
+    /// Converts calendar time in this time zone to Unix time.
+    ///
+    /// Second 60 is the leap second if the zone has one at that time, otherwise it's normalized
+    /// to the next minute like in `mktime`. Use [`resolve_local`](Self::resolve_local) to tell
+    /// these apart.
+    ///
+    /// Skipped local time with unknown DST flag fails with [`TzError::Nonexistent`].
+    pub fn from_local(&self, time: LocalDateTime) -> Result<time_t, TzError> {
+        let mut tm = libc::tm::from(time);
+        let mut out = 0;
+        // C functions happily modify the inputs... Garbage everywhere...
+        match unsafe { ffi::rl_mktime_z(self.state.as_ptr(), &mut tm, &mut out) } {
+            0 => Ok(out),
+            // the C code reports skipped time the same way as overflow
+            _ => match self.resolve_local(time) {
+                Ok(LocalResult::Gap(_, _)) => Err(TzError::Nonexistent),
+                _ => Err(TzError::Overflow),
+            },
+        }
+    }
+
+    /// Returns an iterator over transitions of this zone happening in `range`.
+    ///
+    /// A transition happens when the UT offset, DST flag or abbreviation changes. The iterator
+    /// includes transitions computed from the rules of the zone after the last stored one.
+    pub fn transitions<R: RangeBounds<time_t>>(&self, range: R) -> Transitions<'_> {
+        Transitions::new(self, range.start_bound().cloned(), range.end_bound().cloned())
+    }
+
+    /// Returns an iterator over the leap seconds of this zone.
+    ///
+    /// Only zones from the `right/` directory of the tz database have leap seconds.
+    pub fn leap_seconds(&self) -> LeapSeconds<'_> {
+        LeapSeconds::new(self)
+    }
+
+    /// Returns the leap seconds of this zone as a table.
+    ///
+    /// The expiration is known if the zone file records it. The table is empty for zones that
+    /// are not from `right/`.
+    pub fn leap_table(&self) -> LeapTable {
+        LeapTable::from_zone(self)
+    }
+
+    /// Converts time counting leap seconds of this zone to POSIX time which doesn't.
+    ///
+    /// An inserted leap second has the same POSIX time as the second before it. This is
+    /// `time2posix_z` from the C library. Fails only if the result doesn't fit into `time_t`.
+    pub fn to_posix(&self, t: time_t) -> Result<time_t, TzError> {
+        if !leap::can_correct(self, t) {
+            return Err(TzError::Overflow);
+        }
+        Ok(unsafe { ffi::rl_time2posix_z(self.state.as_ptr(), t) })
+    }
+
+    /// Converts POSIX time to time counting leap seconds of this zone.
+    ///
+    /// This is the inverse of [`to_posix`](Self::to_posix) returning the earlier second if two
+    /// seconds have the same POSIX time and the adjacent second for a removed leap second. This is
+    /// `posix2time_z` from the C library.
+    pub fn from_posix(&self, t: time_t) -> Result<time_t, TzError> {
+        if !leap::can_correct(self, t) {
+            return Err(TzError::Overflow);
+        }
+        Ok(unsafe { ffi::rl_posix2time_z(self.state.as_ptr(), t) })
+    }
+
+    /// Returns this zone with its leap seconds replaced by the ones in `table`.
+    ///
+    /// Times used with the returned zone count the leap seconds like the zones from `right/`, so
+    /// e.g. [`to_posix`](Self::to_posix) converts them to POSIX time. The transitions of this zone
+    /// are moved accordingly. An empty table turns a `right/` zone into the usual one.
+    ///
+    /// The expiration of the table is kept so that [`leap_table`](Self::leap_table) returns it.
+    pub fn with_leap_table(&self, table: &LeapTable) -> Result<TimeZone, TzError> {
+        let leap_seconds = table.leap_seconds();
+        let mut trans = leap_seconds.iter().map(LeapSecond::at).collect::<Vec<_>>();
+        let mut corr = leap_seconds.iter().map(LeapSecond::correction).collect::<Vec<_>>();
+        if let Some(expires) = table.expires() {
+            // recorded as a leap second not changing the correction like in TZif files
+            let last = corr.last().copied().unwrap_or(0);
+            let at = expires.checked_add(last as time_t).ok_or(TzError::Overflow)?;
+            match trans.last() {
+                Some(&previous) if previous >= at => (),
+                _ => {
+                    trans.push(at);
+                    corr.push(last);
+                },
+            }
+        }
+        let count = c_int::try_from(trans.len()).map_err(|_| TzError::Overflow)?;
+        unsafe {
+            if let Some(state) = NonNull::new(ffi::rl_tzalloc_leaps(self.state.as_ptr(), trans.as_ptr(), corr.as_ptr(), count)) {
+                return Ok(TimeZone { state });
+            }
+        }
+        Err(TzError::ZoneLoad(io::Error::last_os_error()))
+    }
+
+    /// Finds all instants that have the given local time in this time zone.
+    ///
+    /// Unlike [`from_local`](Self::from_local) this ignores the offset and DST flag of `time`
+    /// and reports repeated or skipped local time explicitly. Second 60 that isn't a leap second
+    /// of this zone is reported as skipped.
+    pub fn resolve_local(&self, time: LocalDateTime) -> Result<LocalResult<time_t>, TzError> {
+        Resolver::new(&time, |sec| self.to_local(sec)).resolve()
+    }
+
+    /// Converts calendar time in this time zone to Unix time using the given policy for repeated
+    /// or skipped local time.
+    pub fn from_local_with(&self, time: LocalDateTime, policy: Disambiguation) -> Result<time_t, TzError> {
+        Resolver::new(&time, |sec| self.to_local(sec)).resolve_with(policy)
+    }
+}
+
+impl Drop for TimeZone {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::rl_tzfree(self.state.as_ptr());
+        }
+    }
+}
+
+// The state is never modified after `rl_tzalloc` returns and the `_z` functions don't touch any
+// global variables.
+unsafe impl Send for TimeZone {}
+unsafe impl Sync for TimeZone {}
+
+impl std::fmt::Debug for TimeZone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TimeZone").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TimeZone;
+    use crate::{LocalDateTime, TzError, TzifSection};
+
+    #[test]
+    fn posix_zone() {
+        let zone = TimeZone::load("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        // 2021-01-01 00:00:00 UTC
+        let winter = zone.to_local(1609459200).unwrap();
+        assert_eq!(winter.hour(), 1);
+        assert_eq!(winter.offset(), 3600);
+        assert_eq!(winter.is_dst(), Some(false));
+        assert_eq!(winter.abbreviation(), Some("CET"));
+        assert_eq!(zone.from_local(winter).unwrap(), 1609459200);
+
+        // 2021-07-01 00:00:00 UTC
+        let summer = zone.to_local(1625097600).unwrap();
+        assert_eq!(summer.hour(), 2);
+        assert_eq!(summer.offset(), 7200);
+        assert_eq!(summer.is_dst(), Some(true));
+        assert_eq!(summer.abbreviation(), Some("CEST"));
+        assert_eq!(zone.from_local(summer).unwrap(), 1625097600);
+
+        // one second before the epoch is not an error
+        let before_epoch = zone.to_local(-1).unwrap();
+        assert_eq!(zone.from_local(before_epoch).unwrap(), -1);
+
+        // clocks jumped from 02:00 to 03:00 at 2021-03-28 01:00:00 UTC
+        let skipped = LocalDateTime::new(2021, 3, 28, 2, 30, 0).unwrap();
+        assert!(matches!(zone.from_local(skipped), Err(TzError::Nonexistent)));
+    }
+
+    #[test]
+    fn tzif() {
+        // version 2 file with a single transition from LMT to CET at 1970-01-01 00:00:00 UTC and
+        // a footer
+        let mut data = Vec::new();
+        for &(stored, abbrs) in &[(4, &b"LMT\0CET\0"[..]), (8, &b"LMT\0CET\0"[..])] {
+            data.extend_from_slice(b"TZif2");
+            data.extend_from_slice(&[0; 15]);
+            // isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt
+            for &count in &[0u32, 0, 0, 1, 2, 8] {
+                data.extend_from_slice(&count.to_be_bytes());
+            }
+            data.extend_from_slice(&vec![0; stored]);
+            data.push(1);
+            data.extend_from_slice(&[0, 0, 4, 0x30, 0, 0]);
+            data.extend_from_slice(&[0, 0, 0x0e, 0x10, 0, 4]);
+            data.extend_from_slice(abbrs);
+        }
+        data.extend_from_slice(b"\nCET-1CEST,M3.5.0,M10.5.0/3\n");
+
+        let zone = TimeZone::from_tzif(&data).unwrap();
+        assert_eq!(zone.to_local(-1).unwrap().abbreviation(), Some("LMT"));
+        assert_eq!(zone.to_local(-1).unwrap().offset(), 1072);
+        assert_eq!(zone.to_local(1609459200).unwrap().abbreviation(), Some("CET"));
+        assert_eq!(zone.to_local(1625097600).unwrap().abbreviation(), Some("CEST"));
+
+        let error = |data: &[u8]| match TimeZone::from_tzif(data) {
+            Err(TzError::Tzif(error)) => error.section(),
+            other => panic!("unexpected result: {:?}", other),
+        };
+        assert_eq!(error(b"TZif"), TzifSection::Header);
+        let mut bad_magic = data.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(error(&bad_magic), TzifSection::Header);
+        let mut bad_type = data.clone();
+        bad_type[44 + 4] = 2;
+        assert_eq!(error(&bad_type), TzifSection::TransitionTypes);
+        let mut bad_dst = data;
+        bad_dst[44 + 4 + 1 + 4] = 2;
+        assert_eq!(error(&bad_dst), TzifSection::LocalTimeTypes);
+    }
+
+    #[test]
+    fn large_tzif() {
+        // version 1 file switching between STD and DST daily, more than the C code used to allow
+        let (timecnt, leapcnt, charcnt) = (3000u32, 60u32, 64u32);
+        let mut data = b"TZif".to_vec();
+        data.extend_from_slice(&[0; 16]);
+        // isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt
+        for &count in &[0, 0, leapcnt, timecnt, 2, charcnt] {
+            data.extend_from_slice(&count.to_be_bytes());
+        }
+        for i in 0..timecnt {
+            data.extend_from_slice(&(i * 86400).to_be_bytes());
+        }
+        data.extend((0..timecnt).map(|i| (i % 2) as u8));
+        data.extend_from_slice(&[0, 0, 0x0e, 0x10, 0, 0]);
+        data.extend_from_slice(&[0, 0, 0x1c, 0x20, 1, 4]);
+        data.extend_from_slice(b"STD\0DST\0");
+        data.resize(data.len() + charcnt as usize - 8, 0);
+        // all leap seconds are after the checked times
+        for i in 0..leapcnt {
+            data.extend_from_slice(&(400_000_000 + i * 1_000_000).to_be_bytes());
+            data.extend_from_slice(&(i + 1).to_be_bytes());
+        }
+
+        let zone = TimeZone::from_tzif(&data).unwrap();
+        let std = zone.to_local(2500 * 86400 + 10).unwrap();
+        assert_eq!((std.offset(), std.abbreviation()), (3600, Some("STD")));
+        let dst = zone.to_local(2999 * 86400 + 10).unwrap();
+        assert_eq!((dst.offset(), dst.abbreviation()), (7200, Some("DST")));
+    }
+}
@@ -0,0 +1,146 @@
+//! Directories searched for zone files.
+
+use std::ffi::{OsStr, OsString};
+use std::path::PathBuf;
+use std::sync::{RwLock, PoisonError};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Directories searched when neither the application nor `TZDIR` specify them.
+///
+/// The last one is the default of the C code.
+const DEFAULT_PATH: &[&str] = &[
+    "/usr/share/zoneinfo",
+    "/usr/lib/zoneinfo",
+    "/usr/share/lib/zoneinfo",
+    "/usr/local/etc/zoneinfo",
+];
+
+/// The path set by the application, `None` if it wasn't set.
+static SEARCH_PATH: RwLock<Option<Vec<PathBuf>>> = RwLock::new(None);
+
+/// Incremented every time `SEARCH_PATH` changes.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Sets the directories searched for zone files in the given order.
+///
+/// This overrides both the `TZDIR` environment variable and the default directories. It affects
+/// [`TimeZone::load`](crate::TimeZone::load) as well as the local time zone which is loaded again
+/// on the next conversion.
+pub fn set_zoneinfo_path<I>(dirs: I) where I: IntoIterator, I::Item: Into<PathBuf> {
+    let dirs = dirs.into_iter().map(Into::into).collect();
+    *SEARCH_PATH.write().unwrap_or_else(PoisonError::into_inner) = Some(dirs);
+    GENERATION.fetch_add(1, Ordering::Release);
+    crate::local::invalidate();
+}
+
+/// Undoes [`set_zoneinfo_path`] so the directories are chosen the default way again.
+pub fn reset_zoneinfo_path() {
+    *SEARCH_PATH.write().unwrap_or_else(PoisonError::into_inner) = None;
+    GENERATION.fetch_add(1, Ordering::Release);
+    crate::local::invalidate();
+}
+
+/// Returns the directories searched for zone files in order.
+///
+/// These are the directories set by [`set_zoneinfo_path`] if it was called, otherwise the value
+/// of `TZDIR` from the [`EnvSource`](crate::EnvSource) if it's set and non-empty, otherwise `/usr/share/zoneinfo`,
+/// `/usr/lib/zoneinfo`, `/usr/share/lib/zoneinfo` and `/usr/local/etc/zoneinfo`.
+pub fn zoneinfo_path() -> Vec<PathBuf> {
+    if let Some(dirs) = &*SEARCH_PATH.read().unwrap_or_else(PoisonError::into_inner) {
+        return dirs.clone();
+    }
+    match crate::env::var_os("TZDIR") {
+        Some(dir) if !dir.is_empty() => vec![dir.into()],
+        _ => DEFAULT_PATH.iter().map(PathBuf::from).collect(),
+    }
+}
+
+/// Returns a number that changes every time [`set_zoneinfo_path`] or [`reset_zoneinfo_path`] is
+/// called.
+pub(crate) fn generation() -> u64 {
+    GENERATION.load(Ordering::Acquire)
+}
+
+/// Returns the path of the zone file with the relative `name`.
+///
+/// The first existing file wins. If there's none the path in the first directory is returned so
+/// that opening it fails with a sensible error. `None` is returned if there are no directories.
+pub(crate) fn find_zone_file(name: &OsStr) -> Option<PathBuf> {
+    let dirs = zoneinfo_path();
+    dirs.iter()
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+        .or_else(|| dirs.first().map(|dir| dir.join(name)))
+}
+
+/// Returns the path of the file the C code loads for the given value of `TZ`.
+///
+/// `None` is returned for UTC (empty `TZ`) which is never loaded from a file. POSIX TZ strings
+/// are first looked up as files by the C code so the returned path usually doesn't exist for
+/// them.
+pub(crate) fn zone_file_path(name: Option<&OsStr>) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+
+    // must match TZDEFAULT defined in build.rs
+    let name = match name {
+        Some(name) => name.as_bytes(),
+        None => return Some(PathBuf::from("/etc/localtime")),
+    };
+    let name = name.strip_prefix(b":").unwrap_or(name);
+    if name.is_empty() {
+        None
+    } else if name.starts_with(b"/") {
+        Some(PathBuf::from(OsStr::from_bytes(name)))
+    } else {
+        find_zone_file(OsStr::from_bytes(name))
+    }
+}
+
+/// Returns `true` if the zone for the given value of `TZ` is looked up in the zoneinfo
+/// directories, so it depends on `TZDIR`.
+pub(crate) fn is_searched(name: Option<&OsStr>) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+
+    let name = match name {
+        Some(name) => name.as_bytes(),
+        None => return false,
+    };
+    let name = name.strip_prefix(b":").unwrap_or(name);
+    !name.is_empty() && !name.starts_with(b"/")
+}
+
+/// Reads `TZDIR` if the zone for `tz` depends on it.
+pub(crate) fn tzdir_for(tz: Option<&OsStr>) -> Option<OsString> {
+    if is_searched(tz) {
+        crate::env::var_os("TZDIR")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use crate::TimeZone;
+
+    #[test]
+    fn search_path() {
+        let _globals = crate::lock_globals();
+        let dir = std::env::temp_dir().join(format!("rl_localtime-zoneinfo-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("Test")).unwrap();
+        std::fs::write(dir.join("Test/Zone"), crate::tzif::fixed(3600, "ABC")).unwrap();
+
+        let path = vec![dir.join("missing"), dir.clone()];
+        super::set_zoneinfo_path(&path);
+        assert_eq!(super::zoneinfo_path(), path);
+        let zone = TimeZone::load("Test/Zone").unwrap();
+        assert_eq!(zone.to_local(0).unwrap().offset(), 3600);
+        assert_eq!(zone.to_local(0).unwrap().abbreviation(), Some("ABC"));
+
+        super::set_zoneinfo_path(Vec::<PathBuf>::new());
+        assert!(TimeZone::load("Test/Zone").is_err());
+        super::reset_zoneinfo_path();
+        assert_ne!(super::zoneinfo_path(), path);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}